@@ -1,10 +1,16 @@
-use crate::token::{Annotation, Location, Token};
+use crate::token::{Annotation, Float, Location, Radix, Token};
+use std::fmt;
+use std::hash::{Hash, Hasher};
 
 // 抽象構文木(AST)を表すデータ型
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Astkind {
-    // 数値
-    Number(u64),
+    // 数値。元の表記を再現できるよう基数も持っておく
+    Number(u64, Radix),
+    // 浮動小数点数
+    Float(Float),
+    // 変数
+    Variable(String),
     // 単項演算
     UnaryOperation {
         operation: UnaryOperation,
@@ -16,6 +22,10 @@ pub enum Astkind {
         left: Box<Ast>,
         right: Box<Ast>,
     },
+    // 括弧で囲まれた式。位置は括弧を含む
+    Paren(Box<Ast>),
+    // パースに失敗した部分の代わりに置く
+    Error,
 }
 
 pub type Ast = Annotation<Astkind>;
@@ -24,7 +34,27 @@ pub type Ast = Annotation<Astkind>;
 impl Ast {
     pub fn number(n: u64, loc: Location) -> Self {
         // impl<T> Annotation<T> で実装したnewを呼ぶ
-        Self::new(Astkind::Number(n), loc)
+        Self::new(Astkind::Number(n, Radix::Decimal), loc)
+    }
+
+    pub fn number_with_radix(n: u64, radix: Radix, loc: Location) -> Self {
+        Self::new(Astkind::Number(n, radix), loc)
+    }
+
+    pub fn float(n: f64, loc: Location) -> Self {
+        Self::new(Astkind::Float(Float(n)), loc)
+    }
+
+    pub fn variable(name: &str, loc: Location) -> Self {
+        Self::new(Astkind::Variable(name.to_string()), loc)
+    }
+
+    pub fn error(loc: Location) -> Self {
+        Self::new(Astkind::Error, loc)
+    }
+
+    pub fn paren(expression: Ast, loc: Location) -> Self {
+        Self::new(Astkind::Paren(Box::new(expression)), loc)
     }
 
     pub fn unary_operation(operation: UnaryOperation, expression: Ast, loc: Location) -> Self {
@@ -52,6 +82,472 @@ impl Ast {
             loc,
         )
     }
+
+    // 括弧の節点を取り除き、中の式に置き換える。他の節点の位置はそのまま
+    pub fn strip_parens(self) -> Self {
+        StripParens.fold(self)
+    }
+
+    // すべての節点と演算子の位置をLocation(0, 0)にする
+    pub fn without_locations(self) -> Self {
+        WithoutLocations.fold(self)
+    }
+
+    // 外側の括弧をすべて外した式
+    pub fn without_parens(&self) -> &Ast {
+        match &self.value {
+            Astkind::Paren(e) => e.without_parens(),
+            _ => self,
+        }
+    }
+
+    // - -x と ~~x を x にまとめる。-(-x) のように間に括弧があってもまとめる
+    // 符号なし整数では -x が評価エラーになるので、評価結果が変わりうることを承知の上で呼ぶ
+    pub fn collapse_double_negation(self) -> Self {
+        let Annotation { value, loc } = self;
+        match value {
+            Astkind::UnaryOperation {
+                operation,
+                expression,
+            } => {
+                let e = expression.collapse_double_negation();
+                match &e.without_parens().value {
+                    Astkind::UnaryOperation {
+                        operation: inner,
+                        expression,
+                    } if inner.value == operation.value
+                        && operation.value != UnaryOperationKind::Plus =>
+                    {
+                        (**expression).clone()
+                    }
+                    _ => Ast::unary_operation(operation, e, loc),
+                }
+            }
+            Astkind::BinaryOperation {
+                operation,
+                left,
+                right,
+            } => Ast::binary_operation(
+                operation,
+                left.collapse_double_negation(),
+                right.collapse_double_negation(),
+                loc,
+            ),
+            Astkind::Paren(e) => Ast::paren(e.collapse_double_negation(), loc),
+            value => Ast::new(value, loc),
+        }
+    }
+}
+
+// 文。REPLの1行が1つの文になる
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum StatementKind {
+    // let x = expr
+    Let { name: String, expression: Ast },
+    // 式だけの文
+    Expression(Ast),
+}
+
+pub type Statement = Annotation<StatementKind>;
+
+impl Statement {
+    pub fn let_(name: &str, expression: Ast, loc: Location) -> Self {
+        Self::new(
+            StatementKind::Let {
+                name: name.to_string(),
+                expression,
+            },
+            loc,
+        )
+    }
+
+    pub fn expression(expression: Ast) -> Self {
+        let loc = expression.loc.clone();
+        Self::new(StatementKind::Expression(expression), loc)
+    }
+
+    // すべての位置をLocation(0, 0)にする
+    pub fn without_locations(self) -> Self {
+        match self.value {
+            StatementKind::Let { name, expression } => {
+                Statement::let_(&name, expression.without_locations(), Location(0, 0))
+            }
+            StatementKind::Expression(e) => Statement::expression(e.without_locations()),
+        }
+    }
+}
+
+// ASTを読むだけの走査。各節点でpre_visit、その種類のvisit_*、post_visitの順に呼ぶ
+// 既定のvisit_*は子をvisitするので、必要なものだけ上書きすればよい
+pub trait Visitor {
+    fn visit(&mut self, ast: &Ast) {
+        walk_ast(self, ast)
+    }
+
+    // 子より先に呼ばれる
+    fn pre_visit(&mut self, _ast: &Ast) {}
+
+    // 子の後に呼ばれる
+    fn post_visit(&mut self, _ast: &Ast) {}
+
+    fn visit_number(&mut self, _n: u64, _radix: Radix, _loc: &Location) {}
+
+    fn visit_float(&mut self, _n: Float, _loc: &Location) {}
+
+    fn visit_variable(&mut self, _name: &str, _loc: &Location) {}
+
+    fn visit_unary_operation(
+        &mut self,
+        operation: &UnaryOperation,
+        expression: &Ast,
+        _loc: &Location,
+    ) {
+        self.visit_unary_operator(operation);
+        self.visit(expression);
+    }
+
+    fn visit_binary_operation(
+        &mut self,
+        operation: &BinaryOperation,
+        left: &Ast,
+        right: &Ast,
+        _loc: &Location,
+    ) {
+        self.visit(left);
+        self.visit_binary_operator(operation);
+        self.visit(right);
+    }
+
+    fn visit_unary_operator(&mut self, _operation: &UnaryOperation) {}
+
+    fn visit_binary_operator(&mut self, _operation: &BinaryOperation) {}
+
+    fn visit_paren(&mut self, expression: &Ast, _loc: &Location) {
+        self.visit(expression);
+    }
+
+    fn visit_error(&mut self, _loc: &Location) {}
+}
+
+// Visitor::visitの既定の動作
+pub fn walk_ast<V: Visitor + ?Sized>(visitor: &mut V, ast: &Ast) {
+    visitor.pre_visit(ast);
+    let loc = &ast.loc;
+    match &ast.value {
+        Astkind::Number(n, radix) => visitor.visit_number(*n, *radix, loc),
+        Astkind::Float(n) => visitor.visit_float(*n, loc),
+        Astkind::Variable(name) => visitor.visit_variable(name, loc),
+        Astkind::UnaryOperation {
+            operation,
+            expression,
+        } => visitor.visit_unary_operation(operation, expression, loc),
+        Astkind::BinaryOperation {
+            operation,
+            left,
+            right,
+        } => visitor.visit_binary_operation(operation, left, right, loc),
+        Astkind::Paren(e) => visitor.visit_paren(e, loc),
+        Astkind::Error => visitor.visit_error(loc),
+    }
+    visitor.post_visit(ast);
+}
+
+// ASTを作り直す走査。既定のfold_*は子をfoldして同じ種類の節点を組み立てる
+pub trait Fold {
+    fn fold(&mut self, ast: Ast) -> Ast {
+        fold_ast(self, ast)
+    }
+
+    fn fold_number(&mut self, n: u64, radix: Radix, loc: Location) -> Ast {
+        Ast::number_with_radix(n, radix, loc)
+    }
+
+    fn fold_float(&mut self, n: Float, loc: Location) -> Ast {
+        Ast::new(Astkind::Float(n), loc)
+    }
+
+    fn fold_variable(&mut self, name: String, loc: Location) -> Ast {
+        Ast::new(Astkind::Variable(name), loc)
+    }
+
+    fn fold_unary_operation(
+        &mut self,
+        operation: UnaryOperation,
+        expression: Ast,
+        loc: Location,
+    ) -> Ast {
+        let operation = self.fold_unary_operator(operation);
+        let expression = self.fold(expression);
+        Ast::unary_operation(operation, expression, loc)
+    }
+
+    fn fold_binary_operation(
+        &mut self,
+        operation: BinaryOperation,
+        left: Ast,
+        right: Ast,
+        loc: Location,
+    ) -> Ast {
+        let left = self.fold(left);
+        let operation = self.fold_binary_operator(operation);
+        let right = self.fold(right);
+        Ast::binary_operation(operation, left, right, loc)
+    }
+
+    fn fold_unary_operator(&mut self, operation: UnaryOperation) -> UnaryOperation {
+        operation
+    }
+
+    fn fold_binary_operator(&mut self, operation: BinaryOperation) -> BinaryOperation {
+        operation
+    }
+
+    fn fold_paren(&mut self, expression: Ast, loc: Location) -> Ast {
+        let expression = self.fold(expression);
+        Ast::paren(expression, loc)
+    }
+
+    fn fold_error(&mut self, loc: Location) -> Ast {
+        Ast::error(loc)
+    }
+}
+
+// Fold::foldの既定の動作
+pub fn fold_ast<F: Fold + ?Sized>(folder: &mut F, ast: Ast) -> Ast {
+    let Annotation { value, loc } = ast;
+    match value {
+        Astkind::Number(n, radix) => folder.fold_number(n, radix, loc),
+        Astkind::Float(n) => folder.fold_float(n, loc),
+        Astkind::Variable(name) => folder.fold_variable(name, loc),
+        Astkind::UnaryOperation {
+            operation,
+            expression,
+        } => folder.fold_unary_operation(operation, *expression, loc),
+        Astkind::BinaryOperation {
+            operation,
+            left,
+            right,
+        } => folder.fold_binary_operation(operation, *left, *right, loc),
+        Astkind::Paren(e) => folder.fold_paren(*e, loc),
+        Astkind::Error => folder.fold_error(loc),
+    }
+}
+
+// ASTをその場で書き換える走査。子を書き換えた後にvisit_ast_mutを呼ぶ
+// 既定のvisit_*_mutは子をたどるだけで何も書き換えない。節点の種類を変えるならvisit_ast_mutを使う
+pub trait MutVisitor {
+    fn visit_mut(&mut self, ast: &mut Ast) {
+        walk_ast_mut(self, ast)
+    }
+
+    // 子を書き換えた後の節点ごとに呼ばれる
+    fn visit_ast_mut(&mut self, _ast: &mut Ast) {}
+
+    fn visit_number_mut(&mut self, _n: &mut u64, _radix: &mut Radix, _loc: &mut Location) {}
+
+    fn visit_float_mut(&mut self, _n: &mut Float, _loc: &mut Location) {}
+
+    fn visit_variable_mut(&mut self, _name: &mut String, _loc: &mut Location) {}
+
+    fn visit_unary_operation_mut(
+        &mut self,
+        operation: &mut UnaryOperation,
+        expression: &mut Ast,
+        _loc: &mut Location,
+    ) {
+        self.visit_unary_operator_mut(operation);
+        self.visit_mut(expression);
+    }
+
+    fn visit_binary_operation_mut(
+        &mut self,
+        operation: &mut BinaryOperation,
+        left: &mut Ast,
+        right: &mut Ast,
+        _loc: &mut Location,
+    ) {
+        self.visit_mut(left);
+        self.visit_binary_operator_mut(operation);
+        self.visit_mut(right);
+    }
+
+    fn visit_unary_operator_mut(&mut self, _operation: &mut UnaryOperation) {}
+
+    fn visit_binary_operator_mut(&mut self, _operation: &mut BinaryOperation) {}
+
+    fn visit_paren_mut(&mut self, expression: &mut Ast, _loc: &mut Location) {
+        self.visit_mut(expression);
+    }
+
+    fn visit_error_mut(&mut self, _loc: &mut Location) {}
+}
+
+// MutVisitor::visit_mutの既定の動作
+pub fn walk_ast_mut<V: MutVisitor + ?Sized>(visitor: &mut V, ast: &mut Ast) {
+    let Annotation { value, loc } = ast;
+    match value {
+        Astkind::Number(n, radix) => visitor.visit_number_mut(n, radix, loc),
+        Astkind::Float(n) => visitor.visit_float_mut(n, loc),
+        Astkind::Variable(name) => visitor.visit_variable_mut(name, loc),
+        Astkind::UnaryOperation {
+            operation,
+            expression,
+        } => visitor.visit_unary_operation_mut(operation, expression, loc),
+        Astkind::BinaryOperation {
+            operation,
+            left,
+            right,
+        } => visitor.visit_binary_operation_mut(operation, left, right, loc),
+        Astkind::Paren(e) => visitor.visit_paren_mut(e, loc),
+        Astkind::Error => visitor.visit_error_mut(loc),
+    }
+    visitor.visit_ast_mut(ast);
+}
+
+// 括弧の節点を中の式に置き換える
+struct StripParens;
+
+impl Fold for StripParens {
+    fn fold_paren(&mut self, expression: Ast, _loc: Location) -> Ast {
+        self.fold(expression)
+    }
+}
+
+// すべての位置をLocation(0, 0)にする
+struct WithoutLocations;
+
+impl Fold for WithoutLocations {
+    fn fold(&mut self, ast: Ast) -> Ast {
+        let mut ast = fold_ast(self, ast);
+        ast.loc = Location(0, 0);
+        ast
+    }
+
+    fn fold_unary_operator(&mut self, operation: UnaryOperation) -> UnaryOperation {
+        UnaryOperation::new(operation.value, Location(0, 0))
+    }
+
+    fn fold_binary_operator(&mut self, operation: BinaryOperation) -> BinaryOperation {
+        BinaryOperation::new(operation.value, Location(0, 0))
+    }
+}
+
+// 位置(Location)を無視した比較とハッシュ。空白の付け方だけが違う式を同じものとして扱う
+// 括弧の節点は区別するので、括弧も無視するならstrip_parensしてから比べる
+pub trait StructuralEq {
+    fn structural_eq(&self, other: &Self) -> bool;
+    fn structural_hash<H: Hasher>(&self, state: &mut H);
+}
+
+impl StructuralEq for Ast {
+    fn structural_eq(&self, other: &Self) -> bool {
+        use self::Astkind::*;
+        match (&self.value, &other.value) {
+            (
+                UnaryOperation {
+                    operation: o1,
+                    expression: e1,
+                },
+                UnaryOperation {
+                    operation: o2,
+                    expression: e2,
+                },
+            ) => o1.value == o2.value && e1.structural_eq(e2),
+            (
+                BinaryOperation {
+                    operation: o1,
+                    left: l1,
+                    right: r1,
+                },
+                BinaryOperation {
+                    operation: o2,
+                    left: l2,
+                    right: r2,
+                },
+            ) => o1.value == o2.value && l1.structural_eq(l2) && r1.structural_eq(r2),
+            (Paren(e1), Paren(e2)) => e1.structural_eq(e2),
+            // 残りは子を持たない節点か、種類の違う節点
+            (v1, v2) => v1 == v2,
+        }
+    }
+
+    fn structural_hash<H: Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(&self.value).hash(state);
+        match &self.value {
+            Astkind::Number(n, radix) => {
+                n.hash(state);
+                radix.hash(state);
+            }
+            Astkind::Float(n) => n.hash(state),
+            Astkind::Variable(name) => name.hash(state),
+            Astkind::UnaryOperation {
+                operation,
+                expression,
+            } => {
+                operation.value.hash(state);
+                expression.structural_hash(state);
+            }
+            Astkind::BinaryOperation {
+                operation,
+                left,
+                right,
+            } => {
+                operation.value.hash(state);
+                left.structural_hash(state);
+                right.structural_hash(state);
+            }
+            Astkind::Paren(e) => e.structural_hash(state),
+            Astkind::Error => (),
+        }
+    }
+}
+
+impl StructuralEq for Statement {
+    fn structural_eq(&self, other: &Self) -> bool {
+        match (&self.value, &other.value) {
+            (
+                StatementKind::Let {
+                    name: n1,
+                    expression: e1,
+                },
+                StatementKind::Let {
+                    name: n2,
+                    expression: e2,
+                },
+            ) => n1 == n2 && e1.structural_eq(e2),
+            (StatementKind::Expression(e1), StatementKind::Expression(e2)) => e1.structural_eq(e2),
+            _ => false,
+        }
+    }
+
+    fn structural_hash<H: Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(&self.value).hash(state);
+        match &self.value {
+            StatementKind::Let { name, expression } => {
+                name.hash(state);
+                expression.structural_hash(state);
+            }
+            StatementKind::Expression(e) => e.structural_hash(state),
+        }
+    }
+}
+
+// StructuralEqで比較・ハッシュするラッパー。位置を無視してHashMapのキーにできる
+#[derive(Debug, Clone)]
+pub struct Structural<T>(pub T);
+
+impl<T: StructuralEq> PartialEq for Structural<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.structural_eq(&other.0)
+    }
+}
+
+impl<T: StructuralEq> Eq for Structural<T> {}
+
+impl<T: StructuralEq> Hash for Structural<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.structural_hash(state)
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -60,6 +556,8 @@ pub enum UnaryOperationKind {
     Plus,
     // 負号
     Minus,
+    // ビット反転
+    BitNot,
 }
 
 pub type UnaryOperation = Annotation<UnaryOperationKind>;
@@ -72,6 +570,10 @@ impl UnaryOperation {
     pub fn minus(loc: Location) -> Self {
         Self::new(UnaryOperationKind::Minus, loc)
     }
+
+    pub fn bit_not(loc: Location) -> Self {
+        Self::new(UnaryOperationKind::BitNot, loc)
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -84,6 +586,22 @@ pub enum BinaryOperationKind {
     Mult,
     // 除算
     Div,
+    // 累乗
+    Pow,
+    // 剰余
+    Mod,
+    // 切り捨て除算
+    IntDiv,
+    // ビット論理積
+    BitAnd,
+    // ビット論理和
+    BitOr,
+    // ビット排他的論理和
+    BitXor,
+    // 左シフト
+    Shl,
+    // 右シフト
+    Shr,
 }
 
 pub type BinaryOperation = Annotation<BinaryOperationKind>;
@@ -104,6 +622,198 @@ impl BinaryOperation {
     pub fn div(loc: Location) -> Self {
         Self::new(BinaryOperationKind::Div, loc)
     }
+
+    pub fn pow(loc: Location) -> Self {
+        Self::new(BinaryOperationKind::Pow, loc)
+    }
+
+    pub fn modulo(loc: Location) -> Self {
+        Self::new(BinaryOperationKind::Mod, loc)
+    }
+
+    pub fn int_div(loc: Location) -> Self {
+        Self::new(BinaryOperationKind::IntDiv, loc)
+    }
+
+    pub fn bit_and(loc: Location) -> Self {
+        Self::new(BinaryOperationKind::BitAnd, loc)
+    }
+
+    pub fn bit_or(loc: Location) -> Self {
+        Self::new(BinaryOperationKind::BitOr, loc)
+    }
+
+    pub fn bit_xor(loc: Location) -> Self {
+        Self::new(BinaryOperationKind::BitXor, loc)
+    }
+
+    pub fn shl(loc: Location) -> Self {
+        Self::new(BinaryOperationKind::Shl, loc)
+    }
+
+    pub fn shr(loc: Location) -> Self {
+        Self::new(BinaryOperationKind::Shr, loc)
+    }
+}
+
+// 二項演算子の結合性
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Associativity {
+    Left,
+    Right,
+}
+
+// 標準の演算子表(OperatorTable::standard)はここの優先順位と結合性から作る
+impl BinaryOperationKind {
+    // 結合の強さ。大きいほど強く結合する
+    pub fn precedence(&self) -> u8 {
+        use self::BinaryOperationKind::*;
+        match self {
+            BitOr => 1,
+            BitXor => 2,
+            BitAnd => 3,
+            Shl | Shr => 4,
+            Add | Sub => 5,
+            Mult | Div | IntDiv | Mod => 6,
+            Pow => 8,
+        }
+    }
+
+    pub fn associativity(&self) -> Associativity {
+        match self {
+            BinaryOperationKind::Pow => Associativity::Right,
+            _ => Associativity::Left,
+        }
+    }
+}
+
+// 標準の演算子表での前置演算子の結合の強さ
+pub const PREFIX_PRECEDENCE: u8 = 7;
+
+impl fmt::Display for UnaryOperationKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UnaryOperationKind::Plus => write!(f, "+"),
+            UnaryOperationKind::Minus => write!(f, "-"),
+            UnaryOperationKind::BitNot => write!(f, "~"),
+        }
+    }
+}
+
+impl fmt::Display for BinaryOperationKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::BinaryOperationKind::*;
+        match self {
+            Add => write!(f, "+"),
+            Sub => write!(f, "-"),
+            Mult => write!(f, "*"),
+            Div => write!(f, "/"),
+            Pow => write!(f, "**"),
+            Mod => write!(f, "%"),
+            IntDiv => write!(f, "//"),
+            BitAnd => write!(f, "&"),
+            BitOr => write!(f, "|"),
+            BitXor => write!(f, "^"),
+            Shl => write!(f, "<<"),
+            Shr => write!(f, ">>"),
+        }
+    }
+}
+
+// 式をソースの形で出力する。括弧は優先順位と結合性から必要なところにだけ付け、
+// 元の括弧(Paren節点)は付け直すかどうかを同じ規則で決める
+impl fmt::Display for Astkind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Astkind::Number(n, radix) => f.write_str(&radix.format(*n)),
+            Astkind::Float(n) => n.fmt(f),
+            Astkind::Variable(name) => f.write_str(name),
+            Astkind::Paren(e) => e.value.fmt(f),
+            Astkind::Error => write!(f, "<error>"),
+            Astkind::UnaryOperation {
+                operation,
+                expression,
+            } => {
+                let expression = expression.without_parens();
+                write!(f, "{}", operation.value)?;
+                write_operand(f, expression, unary_operand_needs_paren(expression))
+            }
+            Astkind::BinaryOperation {
+                operation,
+                left,
+                right,
+            } => {
+                let op = &operation.value;
+                let (left, right) = (left.without_parens(), right.without_parens());
+                let left_paren = binary_operand_needs_paren(op, left, Associativity::Left);
+                write_operand(f, left, left_paren)?;
+                write!(f, " {} ", op)?;
+                let right_paren = binary_operand_needs_paren(op, right, Associativity::Right);
+                write_operand(f, right, right_paren)
+            }
+        }
+    }
+}
+
+impl fmt::Display for Ast {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.value.fmt(f)
+    }
+}
+
+// 前置演算子の子に括弧が必要か。**以外の二項演算は前置演算子より弱い
+pub(crate) fn unary_operand_needs_paren(child: &Ast) -> bool {
+    match &child.value {
+        Astkind::BinaryOperation { operation, .. } => {
+            operation.value.precedence() < PREFIX_PRECEDENCE
+        }
+        _ => false,
+    }
+}
+
+// 二項演算opのside側(左ならLeft)の子に括弧が必要か
+pub(crate) fn binary_operand_needs_paren(
+    op: &BinaryOperationKind,
+    child: &Ast,
+    side: Associativity,
+) -> bool {
+    match &child.value {
+        Astkind::BinaryOperation { operation, .. } => {
+            let child = operation.value.precedence();
+            child < op.precedence() || (child == op.precedence() && op.associativity() != side)
+        }
+        // -2 ** 2 は -(2 ** 2) と読まれる。定数畳み込みでできた負の小数も書くと - から始まる
+        Astkind::UnaryOperation { .. } => {
+            side == Associativity::Left && PREFIX_PRECEDENCE < op.precedence()
+        }
+        Astkind::Float(n) if n.0.is_sign_negative() => {
+            side == Associativity::Left && PREFIX_PRECEDENCE < op.precedence()
+        }
+        _ => false,
+    }
+}
+
+fn write_operand(f: &mut fmt::Formatter, e: &Ast, paren: bool) -> fmt::Result {
+    if paren {
+        write!(f, "({})", e)
+    } else {
+        write!(f, "{}", e)
+    }
+}
+
+impl fmt::Display for StatementKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StatementKind::Let { name, expression } => write!(f, "let {} = {}", name, expression),
+            StatementKind::Expression(e) => e.fmt(f),
+        }
+    }
+}
+
+impl fmt::Display for Statement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.value.fmt(f)
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -122,6 +832,45 @@ pub enum ParseError {
     Eof,
 }
 
+impl ParseError {
+    // エラーの原因となったトークンの位置。入力の終端ならNone
+    pub fn loc(&self) -> Option<&Location> {
+        use self::ParseError::*;
+        match self {
+            UnexpectedToken(tok)
+            | NotExpression(tok)
+            | NotOperator(tok)
+            | UnclosedOpenParen(tok)
+            | RedundantExpression(tok) => Some(&tok.loc),
+            Eof => None,
+        }
+    }
+
+    // 位置を含まないエラーの説明
+    pub fn message(&self) -> String {
+        use self::ParseError::*;
+        match self {
+            UnexpectedToken(tok) => format!("{} is not expected", tok.value),
+            NotExpression(tok) => format!("'{}' is not a start of expression", tok.value),
+            NotOperator(tok) => format!("'{}' is not an operator", tok.value),
+            UnclosedOpenParen(tok) => format!("'{}' is not closed", tok.value),
+            RedundantExpression(tok) => format!("redundant expression starting at '{}'", tok.value),
+            Eof => "End of file".to_string(),
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.loc() {
+            Some(loc) => write!(f, "{}: {}", loc, self.message()),
+            None => f.write_str(&self.message()),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -131,18 +880,48 @@ mod tests {
         let test_number = 3;
         let test_location = Location(0, 2);
         let expected_result = Annotation {
-            value: Astkind::Number(3),
+            value: Astkind::Number(3, Radix::Decimal),
             loc: Location(0, 2),
         };
         let result = Ast::number(test_number, test_location);
         assert_eq!(expected_result, result);
     }
 
+    #[test]
+    fn test_ast_float() {
+        let expected_result = Annotation {
+            value: Astkind::Float(Float(3.5)),
+            loc: Location(0, 3),
+        };
+        let result = Ast::float(3.5, Location(0, 3));
+        assert_eq!(expected_result, result);
+    }
+
+    #[test]
+    fn test_ast_variable() {
+        let expected_result = Annotation {
+            value: Astkind::Variable("x".to_string()),
+            loc: Location(0, 1),
+        };
+        let result = Ast::variable("x", Location(0, 1));
+        assert_eq!(expected_result, result);
+    }
+
+    #[test]
+    fn test_ast_error() {
+        let expected_result = Annotation {
+            value: Astkind::Error,
+            loc: Location(2, 3),
+        };
+        let result = Ast::error(Location(2, 3));
+        assert_eq!(expected_result, result);
+    }
+
     #[test]
     fn test_ast_unary_operation() {
         let test_unary_operation = UnaryOperation::minus(Location(2, 4));
         let test_ast = Annotation {
-            value: Astkind::Number(3),
+            value: Astkind::Number(3, Radix::Decimal),
             loc: Location(4, 5),
         };
         let test_location = Location(0, 2);
@@ -153,7 +932,7 @@ mod tests {
                     loc: Location(2, 4),
                 },
                 expression: Box::new(Annotation {
-                    value: Astkind::Number(3),
+                    value: Astkind::Number(3, Radix::Decimal),
                     loc: Location(4, 5),
                 }),
             },
@@ -180,7 +959,7 @@ mod tests {
                     loc: Location(0, 2),
                 },
                 left: Box::new(Annotation {
-                    value: Astkind::Number(3),
+                    value: Astkind::Number(3, Radix::Decimal),
                     loc: Location(4, 5),
                 }),
                 right: Box::new(Annotation {
@@ -190,7 +969,7 @@ mod tests {
                             loc: Location(6, 7),
                         },
                         expression: Box::new(Annotation {
-                            value: Astkind::Number(8),
+                            value: Astkind::Number(8, Radix::Decimal),
                             loc: Location(9, 10),
                         }),
                     },
@@ -207,6 +986,125 @@ mod tests {
         );
         assert_eq!(expected_result, result);
     }
+    #[test]
+    fn test_collapse_double_negation() {
+        // - -(1 + ~~2)
+        let ast = Ast::unary_operation(
+            UnaryOperation::minus(Location(0, 1)),
+            Ast::unary_operation(
+                UnaryOperation::minus(Location(2, 3)),
+                Ast::binary_operation(
+                    BinaryOperation::add(Location(6, 7)),
+                    Ast::number(1, Location(4, 5)),
+                    Ast::unary_operation(
+                        UnaryOperation::bit_not(Location(8, 9)),
+                        Ast::unary_operation(
+                            UnaryOperation::bit_not(Location(9, 10)),
+                            Ast::number(2, Location(10, 11)),
+                            Location(9, 11),
+                        ),
+                        Location(8, 11),
+                    ),
+                    Location(4, 11),
+                ),
+                Location(2, 12),
+            ),
+            Location(0, 12),
+        );
+        let expected_result = Ast::binary_operation(
+            BinaryOperation::add(Location(6, 7)),
+            Ast::number(1, Location(4, 5)),
+            Ast::number(2, Location(10, 11)),
+            Location(4, 11),
+        );
+        assert_eq!(expected_result, ast.collapse_double_negation());
+
+        // 括弧を挟んだ二重否定もまとめる
+        let ast = crate::parse_str("-(-5) + ~(~(x))").unwrap();
+        assert_eq!(
+            ast.collapse_double_negation(),
+            Ast::binary_operation(
+                BinaryOperation::add(Location(6, 7)),
+                Ast::number(5, Location(3, 4)),
+                Ast::paren(Ast::variable("x", Location(12, 13)), Location(11, 14)),
+                Location(0, 15),
+            )
+        );
+
+        // ---1は内側から畳まれて-1になり、-+1はそのまま
+        let ast = Ast::unary_operation(
+            UnaryOperation::minus(Location(0, 1)),
+            Ast::unary_operation(
+                UnaryOperation::minus(Location(1, 2)),
+                Ast::unary_operation(
+                    UnaryOperation::minus(Location(2, 3)),
+                    Ast::number(1, Location(3, 4)),
+                    Location(2, 4),
+                ),
+                Location(1, 4),
+            ),
+            Location(0, 4),
+        );
+        let expected_result = Ast::unary_operation(
+            UnaryOperation::minus(Location(0, 1)),
+            Ast::number(1, Location(3, 4)),
+            Location(0, 4),
+        );
+        assert_eq!(expected_result, ast.collapse_double_negation());
+        let ast = Ast::unary_operation(
+            UnaryOperation::minus(Location(0, 1)),
+            Ast::unary_operation(
+                UnaryOperation::plus(Location(1, 2)),
+                Ast::number(1, Location(2, 3)),
+                Location(1, 3),
+            ),
+            Location(0, 3),
+        );
+        assert_eq!(ast.clone(), ast.collapse_double_negation());
+    }
+
+    #[test]
+    fn test_strip_parens() {
+        // ((1)) * -(2)
+        let ast = Ast::binary_operation(
+            BinaryOperation::mult(Location(6, 7)),
+            Ast::paren(
+                Ast::paren(Ast::number(1, Location(2, 3)), Location(1, 4)),
+                Location(0, 5),
+            ),
+            Ast::unary_operation(
+                UnaryOperation::minus(Location(8, 9)),
+                Ast::paren(Ast::number(2, Location(10, 11)), Location(9, 12)),
+                Location(8, 12),
+            ),
+            Location(0, 12),
+        );
+        let expected_result = Ast::binary_operation(
+            BinaryOperation::mult(Location(6, 7)),
+            Ast::number(1, Location(2, 3)),
+            Ast::unary_operation(
+                UnaryOperation::minus(Location(8, 9)),
+                Ast::number(2, Location(10, 11)),
+                Location(8, 12),
+            ),
+            Location(0, 12),
+        );
+        assert_eq!(expected_result, ast.strip_parens());
+    }
+
+    #[test]
+    fn test_statement_let() {
+        let expected_result = Annotation {
+            value: StatementKind::Let {
+                name: "x".to_string(),
+                expression: Ast::number(1, Location(8, 9)),
+            },
+            loc: Location(0, 9),
+        };
+        let result = Statement::let_("x", Ast::number(1, Location(8, 9)), Location(0, 9));
+        assert_eq!(expected_result, result);
+    }
+
     #[test]
     fn test_unary_operation_plus() {
         let test_location = Location(0, 2);
@@ -272,4 +1170,359 @@ mod tests {
         let result = BinaryOperation::div(test_location);
         assert_eq!(expect_result, result);
     }
+
+    #[test]
+    fn test_binary_operation_pow() {
+        let test_location = Location(0, 2);
+        let expect_result = Annotation {
+            value: BinaryOperationKind::Pow,
+            loc: Location(0, 2),
+        };
+        let result = BinaryOperation::pow(test_location);
+        assert_eq!(expect_result, result);
+    }
+
+    #[test]
+    fn test_unary_operation_bit_not() {
+        let test_location = Location(0, 2);
+        let expect_result = Annotation {
+            value: UnaryOperationKind::BitNot,
+            loc: Location(0, 2),
+        };
+        let result = UnaryOperation::bit_not(test_location);
+        assert_eq!(expect_result, result);
+    }
+
+    #[test]
+    fn test_binary_operation_modulo() {
+        let test_location = Location(0, 2);
+        let expect_result = Annotation {
+            value: BinaryOperationKind::Mod,
+            loc: Location(0, 2),
+        };
+        let result = BinaryOperation::modulo(test_location);
+        assert_eq!(expect_result, result);
+    }
+
+    #[test]
+    fn test_binary_operation_int_div() {
+        let test_location = Location(0, 2);
+        let expect_result = Annotation {
+            value: BinaryOperationKind::IntDiv,
+            loc: Location(0, 2),
+        };
+        let result = BinaryOperation::int_div(test_location);
+        assert_eq!(expect_result, result);
+    }
+
+    #[test]
+    fn test_binary_operation_bit_and() {
+        let test_location = Location(0, 2);
+        let expect_result = Annotation {
+            value: BinaryOperationKind::BitAnd,
+            loc: Location(0, 2),
+        };
+        let result = BinaryOperation::bit_and(test_location);
+        assert_eq!(expect_result, result);
+    }
+
+    #[test]
+    fn test_binary_operation_bit_or() {
+        let test_location = Location(0, 2);
+        let expect_result = Annotation {
+            value: BinaryOperationKind::BitOr,
+            loc: Location(0, 2),
+        };
+        let result = BinaryOperation::bit_or(test_location);
+        assert_eq!(expect_result, result);
+    }
+
+    #[test]
+    fn test_binary_operation_bit_xor() {
+        let test_location = Location(0, 2);
+        let expect_result = Annotation {
+            value: BinaryOperationKind::BitXor,
+            loc: Location(0, 2),
+        };
+        let result = BinaryOperation::bit_xor(test_location);
+        assert_eq!(expect_result, result);
+    }
+
+    #[test]
+    fn test_binary_operation_shl() {
+        let test_location = Location(0, 2);
+        let expect_result = Annotation {
+            value: BinaryOperationKind::Shl,
+            loc: Location(0, 2),
+        };
+        let result = BinaryOperation::shl(test_location);
+        assert_eq!(expect_result, result);
+    }
+
+    #[test]
+    fn test_binary_operation_shr() {
+        let test_location = Location(0, 2);
+        let expect_result = Annotation {
+            value: BinaryOperationKind::Shr,
+            loc: Location(0, 2),
+        };
+        let result = BinaryOperation::shr(test_location);
+        assert_eq!(expect_result, result);
+    }
+
+    fn reparse(input: &str) -> (String, String) {
+        let ast = crate::parse_str(input).unwrap();
+        let printed = ast.to_string();
+        let reparsed = crate::parse_str(&printed).unwrap();
+        assert!(
+            ast.clone()
+                .strip_parens()
+                .structural_eq(&reparsed.clone().strip_parens()),
+            "{} -> {}",
+            input,
+            printed
+        );
+        (printed, reparsed.to_string())
+    }
+
+    #[test]
+    fn test_display_minimal_parens() {
+        let cases = [
+            ("1+2*3", "1 + 2 * 3"),
+            ("(1+2)*3", "(1 + 2) * 3"),
+            ("((1))", "1"),
+            ("1-(2-3)", "1 - (2 - 3)"),
+            ("(1-2)-3", "1 - 2 - 3"),
+            ("2**(3**2)", "2 ** 3 ** 2"),
+            ("(2**3)**2", "(2 ** 3) ** 2"),
+            ("(-2)**2", "(-2) ** 2"),
+            ("-(2**2)", "-2 ** 2"),
+            ("-(1+x)", "-(1 + x)"),
+            ("2 ** -x", "2 ** -x"),
+            ("(1 | 2) & ~(3 ^ 4)", "(1 | 2) & ~(3 ^ 4)"),
+            ("1 << (2 + 3) // 4.5", "1 << (2 + 3) // 4.5"),
+            ("a xor b % 0x10", "a ^ b % 0x10"),
+            // 整数は書かれた基数のまま出力する
+            ("0xff + 0b1010", "0xFF + 0b1010"),
+            ("0o17 * 1_000", "0o17 * 1000"),
+        ];
+        for (input, expected) in cases {
+            let (printed, reprinted) = reparse(input);
+            assert_eq!(printed, expected);
+            assert_eq!(reprinted, expected);
+        }
+    }
+
+    #[test]
+    fn test_display_statement() {
+        let statement = crate::parse_statement_str("let  x=(1)+ 2").unwrap();
+        assert_eq!(statement.to_string(), "let x = 1 + 2");
+        let statement = crate::parse_statement_str("let x = 0o17").unwrap();
+        assert_eq!(statement.to_string(), "let x = 0o17");
+    }
+
+    #[test]
+    fn test_precedence_matches_standard_table() {
+        use crate::token::{Location, Token};
+        let table = crate::parser::OperatorTable::standard();
+        let tokens = [
+            Token::plus(Location(0, 0)),
+            Token::minus(Location(0, 0)),
+            Token::asterisk(Location(0, 0)),
+            Token::double_asterisk(Location(0, 0)),
+            Token::slash(Location(0, 0)),
+            Token::double_slash(Location(0, 0)),
+            Token::percent(Location(0, 0)),
+            Token::ampersand(Location(0, 0)),
+            Token::pipe(Location(0, 0)),
+            Token::caret(Location(0, 0)),
+            Token::xor(Location(0, 0)),
+            Token::double_less(Location(0, 0)),
+            Token::double_greater(Location(0, 0)),
+        ];
+        for tok in tokens {
+            let op = table.get_infix(&tok.value).unwrap();
+            assert_eq!(op.operation.precedence(), op.precedence);
+            assert_eq!(op.operation.associativity(), op.associativity);
+        }
+        for tok in [Token::plus(Location(0, 0)), Token::tilde(Location(0, 0))] {
+            assert_eq!(
+                table.get_prefix(&tok.value).unwrap().precedence,
+                PREFIX_PRECEDENCE
+            );
+        }
+    }
+
+    #[test]
+    fn test_structural_eq() {
+        let a = crate::parse_str("1+x*(2)").unwrap();
+        let b = crate::parse_str("1 + x  *  ( 2 )").unwrap();
+        assert_ne!(a, b);
+        assert!(a.structural_eq(&b));
+        assert_eq!(Structural(a.clone()), Structural(b.clone()));
+        assert_eq!(a.clone().without_locations(), b.clone().without_locations());
+
+        // 括弧や演算子が違えば別のもの
+        let c = crate::parse_str("1 + x * 2").unwrap();
+        let d = crate::parse_str("1 - x * (2)").unwrap();
+        assert!(!a.structural_eq(&c));
+        assert!(a.clone().strip_parens().structural_eq(&c));
+        assert!(!a.structural_eq(&d));
+
+        let s1 = crate::parse_statement_str("let y = -a").unwrap();
+        let s2 = crate::parse_statement_str("let  y=- a").unwrap();
+        let s3 = crate::parse_statement_str("let z = -a").unwrap();
+        assert!(s1.structural_eq(&s2));
+        assert!(!s1.structural_eq(&s3));
+    }
+
+    #[test]
+    fn test_structural_hash_map_key() {
+        use std::collections::HashMap;
+        let mut cache = HashMap::new();
+        cache.insert(Structural(crate::parse_str("2 ** 10").unwrap()), 1024);
+        let key = Structural(crate::parse_str("2**10").unwrap());
+        assert_eq!(cache.get(&key), Some(&1024));
+        let key = Structural(crate::parse_str("2 ** 11").unwrap());
+        assert_eq!(cache.get(&key), None);
+    }
+
+    // 変数名を集め、節点を開く順と閉じる順を記録する
+    #[derive(Default)]
+    struct Collector {
+        variables: Vec<String>,
+        operators: Vec<BinaryOperationKind>,
+        events: Vec<String>,
+    }
+
+    impl Visitor for Collector {
+        fn pre_visit(&mut self, ast: &Ast) {
+            self.events.push(format!("<{}", ast.loc));
+        }
+
+        fn post_visit(&mut self, ast: &Ast) {
+            self.events.push(format!("{}>", ast.loc));
+        }
+
+        fn visit_variable(&mut self, name: &str, _loc: &Location) {
+            self.variables.push(name.to_string());
+        }
+
+        fn visit_binary_operator(&mut self, operation: &BinaryOperation) {
+            self.operators.push(operation.value.clone());
+        }
+    }
+
+    #[test]
+    fn test_visitor() {
+        let ast = crate::parse_str("a+(b*-c)").unwrap();
+        let mut collector = Collector::default();
+        collector.visit(&ast);
+        assert_eq!(collector.variables, vec!["a", "b", "c"]);
+        assert_eq!(
+            collector.operators,
+            vec![BinaryOperationKind::Add, BinaryOperationKind::Mult]
+        );
+        assert_eq!(
+            collector.events,
+            vec![
+                "<0-8", "<0-1", "0-1>", "<2-8", "<3-7", "<3-4", "3-4>", "<5-7", "<6-7", "6-7>",
+                "5-7>", "3-7>", "2-8>", "0-8>"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_fold() {
+        // 変数を数値に置き換える
+        struct Substitute;
+        impl Fold for Substitute {
+            fn fold_variable(&mut self, name: String, loc: Location) -> Ast {
+                match name.as_str() {
+                    "x" => Ast::number(2, loc),
+                    _ => Ast::variable(&name, loc),
+                }
+            }
+        }
+        let ast = crate::parse_str("x * (y + x)").unwrap();
+        let expected = Ast::binary_operation(
+            BinaryOperation::mult(Location(2, 3)),
+            Ast::number(2, Location(0, 1)),
+            Ast::paren(
+                Ast::binary_operation(
+                    BinaryOperation::add(Location(7, 8)),
+                    Ast::variable("y", Location(5, 6)),
+                    Ast::number(2, Location(9, 10)),
+                    Location(5, 10),
+                ),
+                Location(4, 11),
+            ),
+            Location(0, 11),
+        );
+        assert_eq!(Substitute.fold(ast), expected);
+    }
+
+    #[test]
+    fn test_mut_visitor() {
+        // 位置を右にずらす
+        struct Shift(usize);
+        impl MutVisitor for Shift {
+            fn visit_ast_mut(&mut self, ast: &mut Ast) {
+                ast.loc = Location(ast.loc.0 + self.0, ast.loc.1 + self.0);
+            }
+
+            fn visit_binary_operator_mut(&mut self, operation: &mut BinaryOperation) {
+                operation.loc = Location(operation.loc.0 + self.0, operation.loc.1 + self.0);
+            }
+        }
+        let mut ast = crate::parse_str("1+2").unwrap();
+        Shift(10).visit_mut(&mut ast);
+        assert_eq!(
+            ast,
+            Ast::binary_operation(
+                BinaryOperation::add(Location(11, 12)),
+                Ast::number(1, Location(10, 11)),
+                Ast::number(2, Location(12, 13)),
+                Location(10, 13),
+            )
+        );
+    }
+
+    #[test]
+    fn test_mut_visitor_per_kind() {
+        // 変数の名前だけを変え、ほかの節点は既定の動作でたどる
+        struct Rename;
+        impl MutVisitor for Rename {
+            fn visit_variable_mut(&mut self, name: &mut String, _loc: &mut Location) {
+                name.push('1');
+            }
+        }
+        let mut ast = crate::parse_str("-(x + 2) * 1.5 + ~y").unwrap();
+        Rename.visit_mut(&mut ast);
+        assert_eq!(ast.to_string(), "-(x1 + 2) * 1.5 + ~y1");
+
+        // 整数だけを2倍にする。小数と基数はそのまま
+        struct Double;
+        impl MutVisitor for Double {
+            fn visit_number_mut(&mut self, n: &mut u64, _radix: &mut Radix, _loc: &mut Location) {
+                *n *= 2;
+            }
+        }
+        let mut ast = crate::parse_str("(0x10 + x) * -3 ** 2.0").unwrap();
+        Double.visit_mut(&mut ast);
+        assert_eq!(ast.to_string(), "(0x20 + x) * -6 ** 2.0");
+
+        // エラーの節点だけを集める
+        struct Errors(Vec<Location>);
+        impl MutVisitor for Errors {
+            fn visit_error_mut(&mut self, loc: &mut Location) {
+                self.0.push(loc.clone());
+            }
+        }
+        let (mut ast, _) =
+            crate::parser::parse_recovering(crate::lexer::lex_recovering("(1 + * 2)").0);
+        let mut errors = Errors(Vec::new());
+        errors.visit_mut(&mut ast);
+        assert_eq!(errors.0, vec![Location(5, 6)]);
+    }
 }
@@ -0,0 +1,105 @@
+use sample_parser::diagnostics::render_in_database;
+use sample_parser::formatter::{format_source, Style};
+use sample_parser::SourceDatabase;
+use std::io::{stdin, Read};
+use std::process::exit;
+
+const USAGE: &str = "usage: expr-fmt [--check] [--width N] [--indent N] [--minimal-parens] \
+                     [--strip-comments] [FILE...]";
+
+// 数値をとるオプションの値を読む
+fn number_arg(args: &mut impl Iterator<Item = String>, name: &str) -> usize {
+    match args.next().and_then(|s| s.parse().ok()) {
+        Some(n) => n,
+        None => {
+            eprintln!("error: {} needs a number\n{}", name, USAGE);
+            exit(2);
+        }
+    }
+}
+
+// ファイルを整形して上書きする。--checkなら整形されていないファイルを表示するだけにする
+// ファイルを指定しなければ標準入力を整形して標準出力に書く
+// 整形されていないファイルやエラーがあれば終了コードは1
+fn main() {
+    let mut style = Style::default();
+    let mut check = false;
+    let mut paths = Vec::new();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--check" => check = true,
+            "--width" => style.width = number_arg(&mut args, "--width"),
+            "--indent" => style.indent = number_arg(&mut args, "--indent"),
+            "--minimal-parens" => style.preserve_parens = false,
+            "--strip-comments" => style.preserve_comments = false,
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return;
+            }
+            s if s.starts_with('-') => {
+                eprintln!("error: unknown option {}\n{}", s, USAGE);
+                exit(2);
+            }
+            _ => paths.push(arg),
+        }
+    }
+
+    let mut db = SourceDatabase::new();
+    if paths.is_empty() {
+        let mut text = String::new();
+        if let Err(e) = stdin().read_to_string(&mut text) {
+            eprintln!("error: <stdin>: {}", e);
+            exit(1);
+        }
+        let file = db.add("<stdin>", &text);
+        match format_source(&text, &style) {
+            Ok(formatted) if check => {
+                if formatted != text {
+                    println!("<stdin>");
+                    exit(1);
+                }
+            }
+            Ok(formatted) => print!("{}", formatted),
+            Err(e) => {
+                eprintln!("{}", render_in_database(&db, file, &e));
+                exit(1);
+            }
+        }
+        return;
+    }
+
+    let mut failed = false;
+    for path in &paths {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) => {
+                eprintln!("error: {}: {}", path, e);
+                failed = true;
+                continue;
+            }
+        };
+        let file = db.add(path, &text);
+        let formatted = match format_source(&text, &style) {
+            Ok(formatted) => formatted,
+            Err(e) => {
+                eprintln!("{}", render_in_database(&db, file, &e));
+                failed = true;
+                continue;
+            }
+        };
+        if formatted == text {
+            continue;
+        }
+        if check {
+            println!("{}", path);
+            failed = true;
+        } else if let Err(e) = std::fs::write(path, formatted) {
+            eprintln!("error: {}: {}", path, e);
+            failed = true;
+        }
+    }
+    if failed {
+        exit(1);
+    }
+}
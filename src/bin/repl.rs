@@ -0,0 +1,84 @@
+use sample_parser::diagnostics::render;
+use sample_parser::{eval_file, parse_statement_str, Environment, SourceDatabase};
+use std::io::{stdin, stdout, BufRead, BufReader, Result, Write};
+
+// プロンプトを表示しユーザーの入力を促す
+fn prompt(s: &str) -> Result<()> {
+    let stdout = stdout();
+    let mut stdout = stdout.lock();
+    stdout.write_all(s.as_bytes())?;
+    stdout.flush()
+}
+
+// 引数のファイルを順に評価し、最後の値を出力する
+// 前のファイルで定義した変数は後のファイルから参照できる
+fn run_files(paths: &[String]) -> bool {
+    let mut db = SourceDatabase::new();
+    for path in paths {
+        match std::fs::read_to_string(path) {
+            Ok(text) => {
+                db.add(path, &text);
+            }
+            Err(e) => {
+                eprintln!("error: {}: {}", path, e);
+                return false;
+            }
+        }
+    }
+
+    let mut env = Environment::new();
+    let mut last = None;
+    for file in db.file_ids() {
+        match eval_file(&db, file, &mut env) {
+            Ok(Some(value)) => last = Some(value),
+            Ok(None) => {}
+            Err(e) => {
+                eprintln!("{}", e.render(&db));
+                return false;
+            }
+        }
+    }
+    if let Some(value) = last {
+        println!("{}", value);
+    }
+    true
+}
+
+fn main() {
+    let paths: Vec<String> = std::env::args().skip(1).collect();
+    if !paths.is_empty() {
+        if !run_files(&paths) {
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let stdin = stdin();
+    let stdin = stdin.lock();
+    let stdin = BufReader::new(stdin);
+    let mut lines = stdin.lines();
+    // 変数は行をまたいで保持する
+    let mut env = Environment::new();
+
+    loop {
+        prompt(">").unwrap();
+        // ユーザーの入力を取得する
+        if let Some(Ok(line)) = lines.next() {
+            // 字句解析とパースを行う
+            let statement = match parse_statement_str(&line) {
+                Ok(statement) => statement,
+                Err(e) => {
+                    eprintln!("{}", render(&line, &e));
+                    continue;
+                }
+            };
+            // 評価して結果を出力する
+            match env.eval_statement(&statement) {
+                Ok(n) => println!("{}", n),
+                Err(e) => eprintln!("{}", render(&line, &e)),
+            }
+        } else {
+            break;
+        }
+    }
+}
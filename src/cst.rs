@@ -0,0 +1,545 @@
+use crate::ast::{Ast, Astkind, BinaryOperation, ParseError, Statement, UnaryOperation};
+use crate::lexer::lex_lossless;
+use crate::parser::Parser;
+use crate::source::SourceFile;
+use crate::token::{Annotation, Location, LosslessToken, Token, TokenKind, Trivia};
+use crate::Error;
+use std::collections::BTreeMap;
+use std::fmt;
+
+// 具象構文木(CST)。ASTの各節点に、その節点を作ったトークンを空白ごと持たせたもの
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum CstKind {
+    Number(LosslessToken),
+    Float(LosslessToken),
+    Variable(LosslessToken),
+    UnaryOperation {
+        operation: UnaryOperation,
+        token: LosslessToken,
+        expression: Box<Cst>,
+    },
+    BinaryOperation {
+        operation: BinaryOperation,
+        token: LosslessToken,
+        left: Box<Cst>,
+        right: Box<Cst>,
+    },
+    // 閉じていない括弧はrparenがNone
+    Paren {
+        lparen: LosslessToken,
+        expression: Box<Cst>,
+        rparen: Option<LosslessToken>,
+    },
+    // パースに失敗した部分。読まずに残したトークンの位置なら空
+    Error(Vec<LosslessToken>),
+}
+
+pub type Cst = Annotation<CstKind>;
+
+impl Cst {
+    // トークンとトリビアを取り除いてASTにする
+    pub fn to_ast(&self) -> Ast {
+        let loc = self.loc.clone();
+        match &self.value {
+            CstKind::Number(tok) | CstKind::Float(tok) | CstKind::Variable(tok) => {
+                match &tok.token.value {
+                    TokenKind::Number(n, radix) => Ast::number_with_radix(*n, *radix, loc),
+                    TokenKind::Float(n) => Ast::new(Astkind::Float(*n), loc),
+                    TokenKind::Ident(name) => Ast::variable(name, loc),
+                    _ => Ast::error(loc),
+                }
+            }
+            CstKind::UnaryOperation {
+                operation,
+                expression,
+                ..
+            } => Ast::unary_operation(operation.clone(), expression.to_ast(), loc),
+            CstKind::BinaryOperation {
+                operation,
+                left,
+                right,
+                ..
+            } => Ast::binary_operation(operation.clone(), left.to_ast(), right.to_ast(), loc),
+            CstKind::Paren { expression, .. } => Ast::paren(expression.to_ast(), loc),
+            CstKind::Error(_) => Ast::error(loc),
+        }
+    }
+
+    // 部分木に含まれるトークン。順番は入力中の順とは限らない
+    fn collect_tokens<'a>(&'a self, tokens: &mut Vec<&'a LosslessToken>) {
+        match &self.value {
+            CstKind::Number(tok) | CstKind::Float(tok) | CstKind::Variable(tok) => tokens.push(tok),
+            CstKind::UnaryOperation {
+                token, expression, ..
+            } => {
+                tokens.push(token);
+                expression.collect_tokens(tokens);
+            }
+            CstKind::BinaryOperation {
+                token, left, right, ..
+            } => {
+                left.collect_tokens(tokens);
+                tokens.push(token);
+                right.collect_tokens(tokens);
+            }
+            CstKind::Paren {
+                lparen,
+                expression,
+                rparen,
+            } => {
+                tokens.push(lparen);
+                expression.collect_tokens(tokens);
+                tokens.extend(rparen);
+            }
+            CstKind::Error(toks) => tokens.extend(toks),
+        }
+    }
+}
+
+// 文のCST。letの名前や=が欠けているときはNoneにする
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum CstStatementKind {
+    Let {
+        let_token: LosslessToken,
+        name: Option<LosslessToken>,
+        equal: Option<LosslessToken>,
+        expression: Box<Cst>,
+    },
+    Expression(Cst),
+}
+
+pub type CstStatement = Annotation<CstStatementKind>;
+
+impl CstStatement {
+    // トークンとトリビアを取り除いて文にする
+    // 名前か=が欠けたletは文にできないのでNone
+    pub fn to_statement(&self) -> Option<Statement> {
+        match &self.value {
+            CstStatementKind::Let {
+                name: Some(name),
+                equal: Some(_),
+                expression,
+                ..
+            } => match &name.token.value {
+                TokenKind::Ident(name) => {
+                    Some(Statement::let_(name, expression.to_ast(), self.loc.clone()))
+                }
+                _ => None,
+            },
+            CstStatementKind::Let { .. } => None,
+            CstStatementKind::Expression(e) => Some(Statement::expression(e.to_ast())),
+        }
+    }
+
+    fn collect_tokens<'a>(&'a self, tokens: &mut Vec<&'a LosslessToken>) {
+        match &self.value {
+            CstStatementKind::Let {
+                let_token,
+                name,
+                equal,
+                expression,
+            } => {
+                tokens.push(let_token);
+                tokens.extend(name);
+                tokens.extend(equal);
+                expression.collect_tokens(tokens);
+            }
+            CstStatementKind::Expression(e) => e.collect_tokens(tokens),
+        }
+    }
+}
+
+// 入力全体のCST。書き出すと元の入力と1文字も違わない
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SyntaxTree {
+    // eval_fileと同じ規則で行ごとに区切った文
+    pub statements: Vec<CstStatement>,
+    // エラーからの回復で読み飛ばされ、どの節点にも属さないトークン
+    pub skipped: Vec<LosslessToken>,
+    // 最後のトークンより後ろのトリビア
+    pub trailing: Vec<Trivia>,
+}
+
+impl SyntaxTree {
+    // 標準の演算子表で、エラーから回復しながらパースする
+    // 木は常に作られ、字句解析とパースのエラーはこの順に返す
+    pub fn parse(input: &str) -> (Self, Vec<Error>) {
+        let (tokens, trailing, lex_errors) = lex_lossless(input);
+        let plain: Vec<Token> = tokens.iter().map(|t| t.token.clone()).collect();
+        let mut tokens: BTreeMap<usize, LosslessToken> =
+            tokens.into_iter().map(|t| (t.token.loc.0, t)).collect();
+
+        let parser = Parser::default();
+        let mut parse_errors = Vec::new();
+        let statements = crate::statement_ranges(&SourceFile::new(input), &plain)
+            .into_iter()
+            .map(|range| build_statement(&parser, &plain[range], &mut tokens, &mut parse_errors))
+            .collect();
+        let tree = SyntaxTree {
+            statements,
+            skipped: tokens.into_values().collect(),
+            trailing,
+        };
+        let errors = lex_errors
+            .into_iter()
+            .map(Error::from)
+            .chain(parse_errors.into_iter().map(Error::from))
+            .collect();
+        (tree, errors)
+    }
+
+    // 文にできなかった壊れたletは除く。そのエラーはparseが返している
+    pub fn to_statements(&self) -> Vec<Statement> {
+        self.statements
+            .iter()
+            .filter_map(CstStatement::to_statement)
+            .collect()
+    }
+
+    // 木に含まれるすべてのトークンを入力中の順に並べる
+    pub fn tokens(&self) -> Vec<&LosslessToken> {
+        let mut tokens = Vec::new();
+        for statement in &self.statements {
+            statement.collect_tokens(&mut tokens);
+        }
+        tokens.extend(&self.skipped);
+        tokens.sort_by_key(|t| t.token.loc.0);
+        tokens
+    }
+}
+
+impl fmt::Display for SyntaxTree {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for token in self.tokens() {
+            token.fmt(f)?;
+        }
+        for trivia in &self.trailing {
+            f.write_str(trivia.text())?;
+        }
+        Ok(())
+    }
+}
+
+// 1文ぶんのトークンをParser::parse_statementと同じ規則で読む
+// letの頭が壊れていても残りは式として読み、頭のエラーは1つだけ報告する
+fn build_statement(
+    parser: &Parser,
+    statement: &[Token],
+    tokens: &mut BTreeMap<usize, LosslessToken>,
+    errors: &mut Vec<ParseError>,
+) -> CstStatement {
+    let loc = match (statement.first(), statement.last()) {
+        (Some(first), Some(last)) => first.loc.merge(&last.loc),
+        _ => Location(0, 0),
+    };
+    let head = match statement.split_first() {
+        Some((first, rest)) if first.value == TokenKind::Let => {
+            take(tokens, &first.loc).map(|tok| (tok, rest))
+        }
+        _ => None,
+    };
+    let Some((let_token, mut rest)) = head else {
+        let expression = build_expression(parser, statement, tokens, errors);
+        return CstStatement::new(CstStatementKind::Expression(expression), loc);
+    };
+
+    // 名前がなくても続く=は拾う
+    let name = next_token(&mut rest, tokens, |kind| {
+        matches!(kind, TokenKind::Ident(_))
+    });
+    let equal = next_token(&mut rest, tokens, |kind| *kind == TokenKind::Equal);
+    let head_error = match (&name, &equal) {
+        (Err(e), _) | (Ok(_), Err(e)) => Some(e.clone()),
+        _ => None,
+    };
+    let expression = match head_error {
+        // 頭のエラーで文が終わっているなら、式についてもう一度Eofは報告しない
+        Some(e) if rest.is_empty() => {
+            errors.push(e);
+            Cst::new(CstKind::Error(Vec::new()), Location(loc.1, loc.1))
+        }
+        head_error => {
+            errors.extend(head_error);
+            build_expression(parser, rest, tokens, errors)
+        }
+    };
+    CstStatement::new(
+        CstStatementKind::Let {
+            let_token,
+            name: name.ok(),
+            equal: equal.ok(),
+            expression: Box::new(expression),
+        },
+        loc,
+    )
+}
+
+// 先頭のトークンがexpectedを満たせば取り出して進める
+fn next_token(
+    rest: &mut &[Token],
+    tokens: &mut BTreeMap<usize, LosslessToken>,
+    expected: fn(&TokenKind) -> bool,
+) -> Result<LosslessToken, ParseError> {
+    let tok = rest.first().ok_or(ParseError::Eof)?;
+    if !expected(&tok.value) {
+        return Err(ParseError::UnexpectedToken(tok.clone()));
+    }
+    let lossless = take(tokens, &tok.loc).ok_or(ParseError::UnexpectedToken(tok.clone()))?;
+    *rest = &rest[1..];
+    Ok(lossless)
+}
+
+fn build_expression(
+    parser: &Parser,
+    expression: &[Token],
+    tokens: &mut BTreeMap<usize, LosslessToken>,
+    errors: &mut Vec<ParseError>,
+) -> Cst {
+    let (ast, parse_errors) = parser.parse_recovering(expression.to_vec());
+    errors.extend(parse_errors);
+    build(&ast, tokens)
+}
+
+// ASTの節点の位置をもとにトークンを割り当てていく
+// 演算子は子より先に取り、Error節点は読まずに残された)を取らないようにする
+// トークンが見つからない節点は空のError節点にし、子のトークンはskippedに残す
+fn build(ast: &Ast, tokens: &mut BTreeMap<usize, LosslessToken>) -> Cst {
+    let loc = ast.loc.clone();
+    let value = match &ast.value {
+        Astkind::Number(..) | Astkind::Float(_) | Astkind::Variable(_) => {
+            match take(tokens, &loc) {
+                Some(tok) => match tok.token.value {
+                    TokenKind::Number(_, _) => CstKind::Number(tok),
+                    TokenKind::Float(_) => CstKind::Float(tok),
+                    _ => CstKind::Variable(tok),
+                },
+                None => CstKind::Error(Vec::new()),
+            }
+        }
+        Astkind::UnaryOperation {
+            operation,
+            expression,
+        } => match take(tokens, &operation.loc) {
+            Some(token) => CstKind::UnaryOperation {
+                operation: operation.clone(),
+                token,
+                expression: Box::new(build(expression, tokens)),
+            },
+            None => CstKind::Error(Vec::new()),
+        },
+        Astkind::BinaryOperation {
+            operation,
+            left,
+            right,
+        } => match take(tokens, &operation.loc) {
+            Some(token) => CstKind::BinaryOperation {
+                operation: operation.clone(),
+                token,
+                left: Box::new(build(left, tokens)),
+                right: Box::new(build(right, tokens)),
+            },
+            None => CstKind::Error(Vec::new()),
+        },
+        Astkind::Paren(expression) => {
+            let Some(lparen) = take(tokens, &Location(loc.0, loc.0 + 1)) else {
+                return Cst::new(CstKind::Error(Vec::new()), loc);
+            };
+            let expression = Box::new(build(expression, tokens));
+            // 内側の括弧が先に自分の)を取るので、残っている)が自分のもの
+            let rparen = tokens
+                .range(..loc.1)
+                .next_back()
+                .filter(|(_, t)| t.token.loc.1 == loc.1 && t.token.value == TokenKind::Rparen)
+                .map(|(&start, _)| start);
+            CstKind::Paren {
+                lparen,
+                expression,
+                rparen: rparen.and_then(|start| tokens.remove(&start)),
+            }
+        }
+        Astkind::Error => {
+            let is_rparen = tokens
+                .get(&loc.0)
+                .is_some_and(|t| t.token.value == TokenKind::Rparen);
+            if is_rparen {
+                CstKind::Error(Vec::new())
+            } else {
+                CstKind::Error(take(tokens, &loc).into_iter().collect())
+            }
+        }
+    };
+    Cst::new(value, loc)
+}
+
+// locにぴったり一致するトークンがまだ残っていれば取り出す
+fn take(tokens: &mut BTreeMap<usize, LosslessToken>, loc: &Location) -> Option<LosslessToken> {
+    if tokens.get(&loc.0).is_some_and(|t| t.token.loc == *loc) {
+        tokens.remove(&loc.0)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::lex_recovering;
+    use crate::parser::parse_recovering;
+    use crate::token::Token;
+
+    fn assert_lossless(input: &str) {
+        let (tree, _) = SyntaxTree::parse(input);
+        assert_eq!(tree.to_string(), input);
+        assert_eq!(tree.tokens().len(), lex_recovering(input).0.len());
+    }
+
+    fn expression(tree: &SyntaxTree) -> &Cst {
+        match &tree.statements[..] {
+            [CstStatement {
+                value: CstStatementKind::Expression(e),
+                ..
+            }] => e,
+            v => panic!("unexpected {:?}", v),
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        assert_lossless("1 + 2 * 3");
+        assert_lossless("  (1+2)\t*  -x ** 0x1F \n");
+        assert_lossless("((1) + ( 2 ))");
+        assert_lossless("// total\n1 /* a */ + # b\n 2 /* open");
+        assert_lossless("");
+        assert_lossless("   ");
+    }
+
+    #[test]
+    fn test_round_trip_with_errors() {
+        assert_lossless("1 + * 2");
+        assert_lossless("(1 + )");
+        assert_lossless("((1)");
+        assert_lossless("(1 2 3) * (4 +");
+        assert_lossless("1 $ 2 )) let =");
+        assert_lossless("\r\n1\r\n");
+        assert_lossless("let = 1\nlet x\nlet 1 = 2\nlet");
+    }
+
+    #[test]
+    fn test_to_ast() {
+        for input in ["1 + 2 * 3", "(1 2 3) * (4 +", "1 + * 2"] {
+            let (tree, _) = SyntaxTree::parse(input);
+            let (ast, _) = parse_recovering(lex_recovering(input).0);
+            assert_eq!(expression(&tree).to_ast(), ast);
+        }
+    }
+
+    #[test]
+    fn test_statements() {
+        let input = "let x = 1 # one\nlet y = x *\n  (2 +\n 3)\ny\n";
+        let (tree, errors) = SyntaxTree::parse(input);
+        assert_eq!(errors, vec![]);
+        assert_eq!(tree.to_string(), input);
+        assert_eq!(tree.statements.len(), 3);
+        match &tree.statements[0].value {
+            CstStatementKind::Let {
+                let_token,
+                name: Some(name),
+                equal: Some(equal),
+                expression,
+            } => {
+                assert_eq!(let_token.to_string(), "let");
+                assert_eq!(name.to_string(), " x");
+                assert_eq!(equal.to_string(), " =");
+                assert_eq!(expression.loc, Location(8, 9));
+            }
+            v => panic!("unexpected {:?}", v),
+        }
+        let expected: Vec<Statement> = ["let x = 1", "let y = x * (2 + 3)", "y"]
+            .into_iter()
+            .map(|s| crate::parse_statement_str(s).unwrap().without_locations())
+            .collect();
+        let statements: Vec<Statement> = tree
+            .to_statements()
+            .into_iter()
+            .map(|s| s.without_locations())
+            .collect();
+        assert_eq!(statements, expected);
+    }
+
+    #[test]
+    fn test_broken_let() {
+        // 名前がなくても=は文に入り、エラーは1つだけ
+        let (tree, errors) = SyntaxTree::parse("let = 1");
+        assert_eq!(
+            errors,
+            vec![Error::Parser(ParseError::UnexpectedToken(Token::equal(
+                Location(4, 5)
+            )))]
+        );
+        match &tree.statements[0].value {
+            CstStatementKind::Let {
+                name: None,
+                equal: Some(_),
+                expression,
+                ..
+            } => assert_eq!(expression.to_ast(), Ast::number(1, Location(6, 7))),
+            v => panic!("unexpected {:?}", v),
+        }
+        assert_eq!(tree.statements[0].to_statement(), None);
+
+        let (tree, errors) = SyntaxTree::parse("let x\n2");
+        assert_eq!(errors, vec![Error::Parser(ParseError::Eof)]);
+        assert_eq!(tree.statements.len(), 2);
+        assert_eq!(tree.to_statements().len(), 1);
+    }
+
+    #[test]
+    fn test_missing_operator_token() {
+        // ASTと合わないトークンはskippedに回り、パニックしない
+        let ast = Ast::binary_operation(
+            crate::ast::BinaryOperation::add(Location(2, 3)),
+            Ast::number(1, Location(0, 1)),
+            Ast::number(2, Location(4, 5)),
+            Location(0, 5),
+        );
+        let (tokens, _, _) = lex_lossless("1 * 2");
+        let mut tokens: BTreeMap<usize, LosslessToken> =
+            tokens.into_iter().map(|t| (t.token.loc.0, t)).collect();
+        tokens.remove(&2);
+        assert_eq!(build(&ast, &mut tokens).value, CstKind::Error(Vec::new()));
+        assert_eq!(tokens.len(), 2);
+    }
+
+    #[test]
+    fn test_syntax_tree() {
+        let (tree, errors) = SyntaxTree::parse("( 1 ) + 2 3");
+        assert_eq!(
+            errors,
+            vec![Error::Parser(crate::ast::ParseError::RedundantExpression(
+                Token::number(3, Location(10, 11))
+            ))]
+        );
+        // 余分な3はどの節点にも入らない
+        assert_eq!(tree.skipped.len(), 1);
+        assert_eq!(tree.skipped[0].to_string(), " 3");
+
+        match &expression(&tree).value {
+            CstKind::BinaryOperation { left, token, .. } => {
+                assert_eq!(token.to_string(), " +");
+                match &left.value {
+                    CstKind::Paren {
+                        lparen,
+                        rparen: Some(rparen),
+                        ..
+                    } => {
+                        assert_eq!(lparen.to_string(), "(");
+                        assert_eq!(rparen.to_string(), " )");
+                    }
+                    v => panic!("unexpected {:?}", v),
+                }
+                assert_eq!(left.loc, Location(0, 5));
+            }
+            v => panic!("unexpected {:?}", v),
+        }
+    }
+}
@@ -0,0 +1,181 @@
+use crate::ast::ParseError;
+use crate::eval::EvalError;
+use crate::source::{FileId, SourceDatabase, SourceFile};
+use crate::token::{LexError, Location};
+use std::fmt;
+
+// 入力中の位置を指し示せるエラー
+pub trait Diagnostic: fmt::Display {
+    // 入力の終端を指す場合はNone
+    fn location(&self) -> Option<&Location>;
+    // 位置を含まないエラーの説明
+    fn message(&self) -> String;
+}
+
+impl Diagnostic for LexError {
+    fn location(&self) -> Option<&Location> {
+        Some(&self.loc)
+    }
+
+    fn message(&self) -> String {
+        self.value.to_string()
+    }
+}
+
+impl Diagnostic for ParseError {
+    fn location(&self) -> Option<&Location> {
+        self.loc()
+    }
+
+    fn message(&self) -> String {
+        ParseError::message(self)
+    }
+}
+
+impl Diagnostic for EvalError {
+    fn location(&self) -> Option<&Location> {
+        Some(&self.loc)
+    }
+
+    fn message(&self) -> String {
+        self.value.to_string()
+    }
+}
+
+// エラーメッセージと該当する入力の行を^^^の下線付きで整形する
+//
+// error: invalid char '$'
+//  --> 1:5
+// 1 + $
+//     ^
+pub fn render<E: Diagnostic>(input: &str, e: &E) -> String {
+    render_source(&SourceFile::new(input), e)
+}
+
+// データベース中のファイルのエラーを、位置をパス:行:列で示して整形する
+//
+// error: variable 'x' is not defined
+//  --> main.expr:2:5
+pub fn render_in_database<E: Diagnostic>(db: &SourceDatabase, file: FileId, e: &E) -> String {
+    render_with_origin(db.file(file), Some(db.path(file)), e)
+}
+
+pub fn render_source<E: Diagnostic>(file: &SourceFile, e: &E) -> String {
+    render_with_origin(file, None, e)
+}
+
+// originがあれば位置の前にパスをつける
+fn render_with_origin<E: Diagnostic>(file: &SourceFile, origin: Option<&str>, e: &E) -> String {
+    let input = file.text();
+    // 位置がなければ入力の終端を指す
+    let (start, end) = match e.location() {
+        Some(loc) => (loc.0, loc.1),
+        None => (input.len(), input.len()),
+    };
+    let start = file.floor_char_boundary(start);
+    let end = file.floor_char_boundary(end).max(start);
+
+    let position = file.line_column_chars(start);
+    let line = file.line_text(position.line);
+    // 下線は開始位置の行の中だけに引く
+    let line_end = file.line_column_chars(end);
+    let width = if line_end.line == position.line {
+        line_end.column - position.column
+    } else {
+        line.chars().count() - position.column
+    };
+
+    let origin = match origin {
+        Some(path) => format!("{}:{}", path, position),
+        None => position.to_string(),
+    };
+    format!(
+        "error: {}\n --> {}\n{}\n{}{}",
+        e.message(),
+        origin,
+        line,
+        " ".repeat(position.column),
+        "^".repeat(width.max(1))
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::lex;
+    use crate::parser::parse;
+    use crate::token::Token;
+
+    #[test]
+    fn test_render_lex_error() {
+        let input = "1 + $";
+        let e = lex(input).unwrap_err();
+        assert_eq!(
+            render(input, &e),
+            "error: invalid char '$'\n --> 1:5\n1 + $\n    ^"
+        );
+    }
+
+    #[test]
+    fn test_render_parse_error() {
+        let input = "1 + 2 3";
+        let e = parse(lex(input).unwrap()).unwrap_err();
+        assert_eq!(
+            render(input, &e),
+            "error: redundant expression starting at '3'\n --> 1:7\n1 + 2 3\n      ^"
+        );
+
+        let input = "1 +";
+        let e = parse(lex(input).unwrap()).unwrap_err();
+        assert_eq!(render(input, &e), "error: End of file\n --> 1:4\n1 +\n   ^");
+    }
+
+    #[test]
+    fn test_render_lone_shift_char() {
+        // 1文字だけの < や > は、続く文字ではなくそれ自身を指す
+        let input = "1 < 2";
+        let e = lex(input).unwrap_err();
+        assert_eq!(
+            render(input, &e),
+            "error: invalid char '<'\n --> 1:3\n1 < 2\n  ^"
+        );
+        let input = "1 >x";
+        let e = lex(input).unwrap_err();
+        assert_eq!(
+            render(input, &e),
+            "error: invalid char '>'\n --> 1:3\n1 >x\n  ^"
+        );
+    }
+
+    #[test]
+    fn test_render_multibyte() {
+        let input = "1 × 2";
+        let e = lex(input).unwrap_err();
+        assert_eq!(
+            render(input, &e),
+            "error: invalid char '×'\n --> 1:3\n1 × 2\n  ^"
+        );
+    }
+
+    #[test]
+    fn test_render_multi_line() {
+        let input = "1 +\n(22 * 3";
+        let e = ParseError::UnexpectedToken(Token::number(22, Location(5, 7)));
+        assert_eq!(
+            render(input, &e),
+            "error: 22 is not expected\n --> 2:2\n(22 * 3\n ^^"
+        );
+    }
+
+    #[test]
+    fn test_render_in_database() {
+        let mut db = SourceDatabase::new();
+        db.add("consts.expr", "let a = 1");
+        let main = db.add("main.expr", "a +\n  2 $");
+        let e = lex(db.file(main).text()).unwrap_err();
+        assert_eq!(
+            render_in_database(&db, main, &e),
+            "error: invalid char '$'\n --> main.expr:2:5\n  2 $\n    ^"
+        );
+    }
+}
@@ -0,0 +1,429 @@
+use crate::ast::{
+    Ast, Astkind, BinaryOperation, BinaryOperationKind, Statement, StatementKind, UnaryOperation,
+    UnaryOperationKind,
+};
+use crate::token::{Annotation, Location};
+use std::collections::HashMap;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum EvalErrorKind {
+    // ゼロ除算
+    DivisionByZero,
+    // u64の範囲を超えた
+    Overflow,
+    // 符号なし整数を負にしようとした
+    NegativeValue,
+    // 束縛されていない変数を参照した
+    UnboundVariable(String),
+    // 負数の非整数乗など結果が定まらない
+    Undefined,
+    // ビット演算に小数を使った
+    NotInteger,
+    // パースに失敗した部分を評価しようとした
+    InvalidExpression,
+}
+
+pub type EvalError = Annotation<EvalErrorKind>;
+
+impl EvalError {
+    pub fn division_by_zero(loc: Location) -> Self {
+        Self::new(EvalErrorKind::DivisionByZero, loc)
+    }
+
+    pub fn overflow(loc: Location) -> Self {
+        Self::new(EvalErrorKind::Overflow, loc)
+    }
+
+    pub fn negative_value(loc: Location) -> Self {
+        Self::new(EvalErrorKind::NegativeValue, loc)
+    }
+
+    pub fn undefined(loc: Location) -> Self {
+        Self::new(EvalErrorKind::Undefined, loc)
+    }
+
+    pub fn not_integer(loc: Location) -> Self {
+        Self::new(EvalErrorKind::NotInteger, loc)
+    }
+
+    pub fn invalid_expression(loc: Location) -> Self {
+        Self::new(EvalErrorKind::InvalidExpression, loc)
+    }
+
+    pub fn unbound_variable(name: &str, loc: Location) -> Self {
+        Self::new(EvalErrorKind::UnboundVariable(name.to_string()), loc)
+    }
+}
+
+impl fmt::Display for EvalErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::EvalErrorKind::*;
+        match self {
+            DivisionByZero => write!(f, "division by zero"),
+            Overflow => write!(f, "integer overflow"),
+            NegativeValue => write!(f, "result is negative"),
+            Undefined => write!(f, "result is undefined"),
+            NotInteger => write!(f, "operand must be an integer"),
+            InvalidExpression => write!(f, "expression has a syntax error"),
+            UnboundVariable(name) => write!(f, "variable '{}' is not defined", name),
+        }
+    }
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.loc, self.value)
+    }
+}
+
+impl std::error::Error for EvalError {}
+
+// 評価結果の値
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Value {
+    Int(u64),
+    Float(f64),
+}
+
+impl Value {
+    fn as_f64(self) -> f64 {
+        match self {
+            Value::Int(n) => n as f64,
+            Value::Float(n) => n,
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Int(n) => n.fmt(f),
+            Value::Float(n) => write!(f, "{:?}", n),
+        }
+    }
+}
+
+// 変数の値を保持する環境。REPLの行をまたいで使い回す
+#[derive(Debug, Clone, Default)]
+pub struct Environment {
+    variables: HashMap<String, Value>,
+}
+
+impl Environment {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, name: &str) -> Option<Value> {
+        self.variables.get(name).copied()
+    }
+
+    pub fn set(&mut self, name: &str, value: Value) {
+        self.variables.insert(name.to_string(), value);
+    }
+
+    // 文を実行する。letの場合は束縛した値を返す
+    pub fn eval_statement(&mut self, statement: &Statement) -> Result<Value, EvalError> {
+        match &statement.value {
+            StatementKind::Let { name, expression } => {
+                let v = self.eval(expression)?;
+                self.set(name, v);
+                Ok(v)
+            }
+            StatementKind::Expression(expression) => self.eval(expression),
+        }
+    }
+
+    // ASTを再帰的に辿って値を計算する
+    pub fn eval(&self, ast: &Ast) -> Result<Value, EvalError> {
+        match &ast.value {
+            Astkind::Number(n, _) => Ok(Value::Int(*n)),
+            Astkind::Float(n) => Ok(Value::Float(n.0)),
+            Astkind::Variable(name) => self
+                .get(name)
+                .ok_or_else(|| EvalError::unbound_variable(name, ast.loc.clone())),
+            Astkind::Paren(e) => self.eval(e),
+            Astkind::Error => Err(EvalError::invalid_expression(ast.loc.clone())),
+            Astkind::UnaryOperation {
+                operation,
+                expression,
+            } => {
+                let e = self.eval(expression)?;
+                eval_unary_operation(operation, e, &ast.loc)
+            }
+            Astkind::BinaryOperation {
+                operation,
+                left,
+                right,
+            } => {
+                let l = self.eval(left)?;
+                let r = self.eval(right)?;
+                eval_binary_operation(operation, l, r, &ast.loc)
+            }
+        }
+    }
+}
+
+// 変数のない式を空の環境で評価する
+pub fn eval(ast: &Ast) -> Result<Value, EvalError> {
+    Environment::new().eval(ast)
+}
+
+fn eval_unary_operation(op: &UnaryOperation, n: Value, loc: &Location) -> Result<Value, EvalError> {
+    match (&op.value, n) {
+        (UnaryOperationKind::Plus, n) => Ok(n),
+        (UnaryOperationKind::Minus, Value::Float(n)) => Ok(Value::Float(-n)),
+        // -0だけは符号なしでも表現できる
+        (UnaryOperationKind::Minus, Value::Int(0)) => Ok(Value::Int(0)),
+        (UnaryOperationKind::Minus, Value::Int(_)) => Err(EvalError::negative_value(loc.clone())),
+        (UnaryOperationKind::BitNot, Value::Int(n)) => Ok(Value::Int(!n)),
+        (UnaryOperationKind::BitNot, Value::Float(_)) => Err(EvalError::not_integer(loc.clone())),
+    }
+}
+
+fn eval_binary_operation(
+    op: &BinaryOperation,
+    l: Value,
+    r: Value,
+    loc: &Location,
+) -> Result<Value, EvalError> {
+    match (l, r) {
+        (Value::Int(l), Value::Int(r)) => eval_int_binary_operation(op, l, r, loc).map(Value::Int),
+        // どちらかが小数なら両方を小数にして計算する
+        (l, r) => eval_float_binary_operation(op, l.as_f64(), r.as_f64(), loc).map(Value::Float),
+    }
+}
+
+fn eval_int_binary_operation(
+    op: &BinaryOperation,
+    l: u64,
+    r: u64,
+    loc: &Location,
+) -> Result<u64, EvalError> {
+    match op.value {
+        BinaryOperationKind::Add => l.checked_add(r).ok_or(EvalError::overflow(loc.clone())),
+        BinaryOperationKind::Sub => l
+            .checked_sub(r)
+            .ok_or(EvalError::negative_value(loc.clone())),
+        BinaryOperationKind::Mult => l.checked_mul(r).ok_or(EvalError::overflow(loc.clone())),
+        BinaryOperationKind::Div => l
+            .checked_div(r)
+            .ok_or(EvalError::division_by_zero(loc.clone())),
+        // 符号なしなので切り捨て除算は通常の除算と同じ
+        BinaryOperationKind::IntDiv => l
+            .checked_div(r)
+            .ok_or(EvalError::division_by_zero(loc.clone())),
+        BinaryOperationKind::Mod => l
+            .checked_rem(r)
+            .ok_or(EvalError::division_by_zero(loc.clone())),
+        BinaryOperationKind::BitAnd => Ok(l & r),
+        BinaryOperationKind::BitOr => Ok(l | r),
+        BinaryOperationKind::BitXor => Ok(l ^ r),
+        // 64ビット以上のシフトはオーバーフローとする
+        BinaryOperationKind::Shl => u32::try_from(r)
+            .ok()
+            .and_then(|r| l.checked_shl(r))
+            .ok_or(EvalError::overflow(loc.clone())),
+        BinaryOperationKind::Shr => u32::try_from(r)
+            .ok()
+            .and_then(|r| l.checked_shr(r))
+            .ok_or(EvalError::overflow(loc.clone())),
+        BinaryOperationKind::Pow => u32::try_from(r)
+            .ok()
+            .and_then(|r| l.checked_pow(r))
+            // 0と1は指数がu32に収まらなくても計算できる
+            .or(if l <= 1 { Some(l) } else { None })
+            .ok_or(EvalError::overflow(loc.clone())),
+    }
+}
+
+fn eval_float_binary_operation(
+    op: &BinaryOperation,
+    l: f64,
+    r: f64,
+    loc: &Location,
+) -> Result<f64, EvalError> {
+    let n = match op.value {
+        BinaryOperationKind::Add => l + r,
+        BinaryOperationKind::Sub => l - r,
+        BinaryOperationKind::Mult => l * r,
+        // 整数と同じく0での除算はエラーにする
+        BinaryOperationKind::Div | BinaryOperationKind::IntDiv | BinaryOperationKind::Mod
+            if r == 0.0 =>
+        {
+            return Err(EvalError::division_by_zero(loc.clone()))
+        }
+        BinaryOperationKind::Div => l / r,
+        // 剰余も切り捨て除算に合わせ、l == (l // r) * r + l % r となるようにする
+        BinaryOperationKind::IntDiv => (l / r).floor(),
+        BinaryOperationKind::Mod => l - r * (l / r).floor(),
+        BinaryOperationKind::Pow => l.powf(r),
+        BinaryOperationKind::BitAnd
+        | BinaryOperationKind::BitOr
+        | BinaryOperationKind::BitXor
+        | BinaryOperationKind::Shl
+        | BinaryOperationKind::Shr => return Err(EvalError::not_integer(loc.clone())),
+    };
+    if n.is_nan() {
+        Err(EvalError::undefined(loc.clone()))
+    } else if n.is_infinite() {
+        Err(EvalError::overflow(loc.clone()))
+    } else {
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::lex;
+    use crate::parser::{parse, parse_statement};
+
+    fn eval_str(input: &str) -> Result<Value, EvalError> {
+        eval(&parse(lex(input).unwrap()).unwrap())
+    }
+
+    #[test]
+    fn test_eval() {
+        assert_eq!(eval_str("1 + 2 * 3"), Ok(Value::Int(7)));
+        assert_eq!(eval_str("(1 + 2) * 3"), Ok(Value::Int(9)));
+        assert_eq!(eval_str("10 - 4 / 2"), Ok(Value::Int(8)));
+        assert_eq!(eval_str("+5 - -0"), Ok(Value::Int(5)));
+        assert_eq!(eval_str("-+-2.5"), Ok(Value::Float(2.5)));
+        assert_eq!(eval_str("~~7"), Ok(Value::Int(7)));
+    }
+
+    #[test]
+    fn test_eval_float() {
+        assert_eq!(eval_str("1.5 * 2"), Ok(Value::Float(3.0)));
+        assert_eq!(eval_str("1 / 4.0"), Ok(Value::Float(0.25)));
+        assert_eq!(eval_str("1 - 2.5"), Ok(Value::Float(-1.5)));
+        assert_eq!(eval_str("-2.5e2 + 1"), Ok(Value::Float(-249.0)));
+        assert_eq!(
+            eval_str("1.5 / 0"),
+            Err(EvalError::division_by_zero(Location(0, 7)))
+        );
+        assert_eq!(
+            eval_str("1e300 * 1e300"),
+            Err(EvalError::overflow(Location(0, 13)))
+        );
+    }
+
+    #[test]
+    fn test_eval_pow() {
+        assert_eq!(eval_str("2 ** 3 ** 2"), Ok(Value::Int(512)));
+        assert_eq!(eval_str("2 * 3 ** 2"), Ok(Value::Int(18)));
+        assert_eq!(eval_str("0 ** 0"), Ok(Value::Int(1)));
+        assert_eq!(eval_str("1 ** 0x1_0000_0000"), Ok(Value::Int(1)));
+        assert_eq!(eval_str("4 ** 0.5"), Ok(Value::Float(2.0)));
+        assert_eq!(eval_str("2 ** -1.0"), Ok(Value::Float(0.5)));
+        assert_eq!(
+            eval_str("2 ** 64"),
+            Err(EvalError::overflow(Location(0, 7)))
+        );
+        assert_eq!(
+            eval_str("-2 ** 2"),
+            Err(EvalError::negative_value(Location(0, 7)))
+        );
+        assert_eq!(eval_str("-4.0 ** 0.5"), Ok(Value::Float(-2.0)));
+        assert_eq!(
+            eval_str("(-2.0) ** 0.5"),
+            Err(EvalError::undefined(Location(0, 13)))
+        );
+    }
+
+    #[test]
+    fn test_eval_bitwise() {
+        assert_eq!(eval_str("0xF0 | 0x0F & 0x3C"), Ok(Value::Int(0xFC)));
+        assert_eq!(eval_str("6 ^ 3"), Ok(Value::Int(5)));
+        assert_eq!(eval_str("6 xor 3 xor 1"), Ok(Value::Int(4)));
+        assert_eq!(eval_str("1 << 4 + 1"), Ok(Value::Int(32)));
+        assert_eq!(eval_str("0xFF >> 4"), Ok(Value::Int(0xF)));
+        assert_eq!(eval_str("~0"), Ok(Value::Int(u64::MAX)));
+        assert_eq!(
+            eval_str("1 << 64"),
+            Err(EvalError::overflow(Location(0, 7)))
+        );
+        assert_eq!(
+            eval_str("1.0 & 1"),
+            Err(EvalError::not_integer(Location(0, 7)))
+        );
+        assert_eq!(
+            eval_str("~1.5"),
+            Err(EvalError::not_integer(Location(0, 4)))
+        );
+    }
+
+    #[test]
+    fn test_eval_mod_and_int_div() {
+        assert_eq!(eval_str("7 % 3"), Ok(Value::Int(1)));
+        assert_eq!(eval_str("7 // 2"), Ok(Value::Int(3)));
+        assert_eq!(eval_str("7.5 // 2"), Ok(Value::Float(3.0)));
+        assert_eq!(eval_str("7.5 % 2"), Ok(Value::Float(1.5)));
+        assert_eq!(eval_str("-7.5 // 2"), Ok(Value::Float(-4.0)));
+        assert_eq!(eval_str("-7.5 % 2"), Ok(Value::Float(0.5)));
+        assert_eq!(eval_str("7.5 % -2.0"), Ok(Value::Float(-0.5)));
+        assert_eq!(
+            eval_str("(-7.5 // 2) * 2 + -7.5 % 2"),
+            Ok(Value::Float(-7.5))
+        );
+        assert_eq!(
+            eval_str("7 % 0"),
+            Err(EvalError::division_by_zero(Location(0, 5)))
+        );
+        assert_eq!(
+            eval_str("7.0 // 0"),
+            Err(EvalError::division_by_zero(Location(0, 8)))
+        );
+    }
+
+    #[test]
+    fn test_eval_statement() {
+        let mut env = Environment::new();
+        let mut run =
+            |input: &str| env.eval_statement(&parse_statement(lex(input).unwrap()).unwrap());
+        assert_eq!(run("let x = 2 * 3"), Ok(Value::Int(6)));
+        assert_eq!(run("let y = x + 0.5"), Ok(Value::Float(6.5)));
+        assert_eq!(run("x * y"), Ok(Value::Float(39.0)));
+        // 再束縛できる
+        assert_eq!(run("let x = x + 1"), Ok(Value::Int(7)));
+        assert_eq!(run("x"), Ok(Value::Int(7)));
+    }
+
+    #[test]
+    fn test_eval_unbound_variable() {
+        assert_eq!(
+            eval_str("1 + foo"),
+            Err(EvalError::unbound_variable("foo", Location(4, 7)))
+        );
+    }
+
+    #[test]
+    fn test_eval_division_by_zero() {
+        assert_eq!(
+            eval_str("1 + 4 / 0"),
+            Err(EvalError::division_by_zero(Location(4, 9)))
+        );
+    }
+
+    #[test]
+    fn test_eval_overflow() {
+        assert_eq!(
+            eval_str("18446744073709551615 + 1"),
+            Err(EvalError::overflow(Location(0, 24)))
+        );
+    }
+
+    #[test]
+    fn test_eval_negative_value() {
+        assert_eq!(
+            eval_str("2 * -3"),
+            Err(EvalError::negative_value(Location(4, 6)))
+        );
+        assert_eq!(
+            eval_str("1 - 2"),
+            Err(EvalError::negative_value(Location(0, 5)))
+        );
+    }
+}
@@ -0,0 +1,460 @@
+use crate::ast::{
+    binary_operand_needs_paren, unary_operand_needs_paren, Associativity, Ast, Astkind,
+    BinaryOperation, BinaryOperationKind, Statement, StatementKind, UnaryOperation, Visitor,
+};
+use crate::lexer::lex_lossless;
+use crate::parser::parse_statement;
+use crate::source::SourceFile;
+use crate::token::{Location, LosslessToken, Token, TokenKind, TriviaKind};
+use crate::{statement_ranges, Error};
+use std::collections::{HashMap, HashSet};
+
+// 整形の設定
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Style {
+    // 1行の最大の文字数。超える式は演算子の後ろで折り返す
+    pub width: usize,
+    // 折り返した行の字下げの幅
+    pub indent: usize,
+    // 書かれた括弧を残す。falseなら必要な括弧だけにする
+    pub preserve_parens: bool,
+    // コメントを残す
+    pub preserve_comments: bool,
+}
+
+impl Default for Style {
+    fn default() -> Self {
+        Self {
+            width: 80,
+            indent: 4,
+            preserve_parens: true,
+            preserve_comments: true,
+        }
+    }
+}
+
+// ファイルの内容を整形する。字句解析かパースに失敗したら最初のエラーを返す
+//
+// 文は1つずつ整形し直し、コメントは元の並びのまま自分の行か直前の文の後ろに置く。
+// 空行は続けて1行までにする
+pub fn format_source(input: &str, style: &Style) -> Result<String, Error> {
+    let source = SourceFile::new(input);
+    let (tokens, trailing, errors) = lex_lossless(input);
+    if let Some(e) = errors.into_iter().next() {
+        return Err(e.into());
+    }
+    let plain: Vec<Token> = tokens.iter().map(|t| t.token.clone()).collect();
+    let literals = tokens
+        .iter()
+        .filter(|t| matches!(t.token.value, TokenKind::Number(..) | TokenKind::Float(_)))
+        .map(|t| (t.token.loc.0, t.text.as_str()))
+        .collect();
+    let printer = Printer { style, literals };
+
+    // 出力する文とコメントを入力中の位置とともに集める
+    let mut items: Vec<(Location, String)> = Vec::new();
+    for range in statement_ranges(&source, &plain) {
+        let statement = parse_statement(plain[range.clone()].to_vec())?;
+        let loc = plain[range.start].loc.merge(&plain[range.end - 1].loc);
+        let inner = &tokens[range.start + 1..range.end];
+        let has_inner_comment = inner
+            .iter()
+            .flat_map(|t| &t.leading)
+            .any(|t| !matches!(t.value, TriviaKind::Whitespace(_)));
+        let text = if has_inner_comment && style.preserve_comments {
+            printer.statement_with_comments(&statement, &tokens[range])
+        } else {
+            printer.statement(&statement)
+        };
+        items.push((loc, text));
+    }
+    if style.preserve_comments {
+        let comments = tokens
+            .iter()
+            .flat_map(|t| &t.leading)
+            .chain(&trailing)
+            .filter(|t| !matches!(t.value, TriviaKind::Whitespace(_)))
+            // 文の途中のコメントは文と一緒に出力済み
+            .filter(|t| {
+                !items
+                    .iter()
+                    .any(|(loc, _)| loc.0 < t.loc.0 && t.loc.1 < loc.1)
+            })
+            .map(|t| (t.loc.clone(), t.text().to_string()))
+            .collect::<Vec<_>>();
+        items.extend(comments);
+    }
+    items.sort_by_key(|(loc, _)| loc.0);
+
+    let mut out = String::new();
+    let mut prev_line = None;
+    for (loc, text) in items {
+        let line = source.line_index(loc.0);
+        match prev_line {
+            // 前の要素と同じ行にあったものは同じ行に続ける
+            Some(prev) if prev == line => out.push(' '),
+            Some(prev) => {
+                out.push('\n');
+                if prev + 1 < line {
+                    out.push('\n');
+                }
+            }
+            None => (),
+        }
+        out.push_str(&text);
+        prev_line = Some(source.line_index(loc.1));
+    }
+    if !out.is_empty() {
+        out.push('\n');
+    }
+    Ok(out)
+}
+
+// 入力がすでに整形済みか
+pub fn is_formatted(input: &str, style: &Style) -> Result<bool, Error> {
+    Ok(format_source(input, style)? == input)
+}
+
+struct Printer<'a> {
+    style: &'a Style,
+    // 数値リテラルの開始位置から書かれたままの綴りを引く。0xff や 1_000 を書き換えない
+    literals: HashMap<usize, &'a str>,
+}
+
+impl Printer<'_> {
+    fn statement(&self, statement: &Statement) -> String {
+        match &statement.value {
+            StatementKind::Let { name, expression } => {
+                let head = format!("let {} = ", name);
+                let column = head.chars().count();
+                head + &self.layout(expression, 0, column)
+            }
+            StatementKind::Expression(e) => self.layout(e, 0, 0),
+        }
+    }
+
+    // 文の途中のコメントは置き場所を決め直せないので、トークンを書かれた順に並べて
+    // 空白だけを整える。括弧は書かれたまま残し、折り返しもしない
+    // 行コメントの後ろは改行して1段字下げする
+    fn statement_with_comments(&self, statement: &Statement, tokens: &[LosslessToken]) -> String {
+        let mut operators = Operators::default();
+        match &statement.value {
+            StatementKind::Let { expression, .. } => operators.visit(expression),
+            StatementKind::Expression(e) => operators.visit(e),
+        }
+        let mut out = String::new();
+        // 次に書くものの前に空白を入れないか
+        let mut glued = true;
+        for (i, tok) in tokens.iter().enumerate() {
+            // 文の前のコメントは文の外で扱う
+            let comments = if i == 0 { &[] } else { tok.leading.as_slice() };
+            for trivia in comments {
+                match trivia.value {
+                    TriviaKind::Whitespace(_) => continue,
+                    _ if !glued => out.push(' '),
+                    _ => (),
+                }
+                out.push_str(trivia.text());
+                glued = matches!(trivia.value, TriviaKind::LineComment(_));
+                if glued {
+                    out.push('\n');
+                    out.push_str(&" ".repeat(self.style.indent));
+                }
+            }
+            let start = tok.token.loc.0;
+            if !glued && tok.token.value != TokenKind::Rparen {
+                out.push(' ');
+            }
+            match (operators.binary.get(&start), &tok.token.value) {
+                (Some(op), _) => out.push_str(op),
+                (None, TokenKind::Number(..) | TokenKind::Float(_)) => out.push_str(&tok.text),
+                (None, value) => out.push_str(&value.to_string()),
+            }
+            glued = tok.token.value == TokenKind::Lparen || operators.unary.contains(&start);
+        }
+        out
+    }
+
+    // 括弧を残さないなら、括弧の節点は見ずに子を扱う
+    fn child<'b>(&self, e: &'b Ast) -> &'b Ast {
+        if self.style.preserve_parens {
+            e
+        } else {
+            e.without_parens()
+        }
+    }
+
+    // 折り返さずに1行で書いた形
+    fn flat(&self, e: &Ast) -> String {
+        match &e.value {
+            Astkind::Paren(inner) if self.style.preserve_parens => {
+                format!("({})", self.flat(inner))
+            }
+            Astkind::Paren(inner) => self.flat(inner),
+            Astkind::UnaryOperation {
+                operation,
+                expression,
+            } => {
+                let child = self.child(expression);
+                let operand = self.flat(child);
+                match unary_operand_needs_paren(child) {
+                    true => format!("{}({})", operation.value, operand),
+                    false => format!("{}{}", operation.value, operand),
+                }
+            }
+            Astkind::BinaryOperation {
+                operation,
+                left,
+                right,
+            } => {
+                let op = &operation.value;
+                let side = |e: &Ast, side| {
+                    let e = self.child(e);
+                    match binary_operand_needs_paren(op, e, side) {
+                        true => format!("({})", self.flat(e)),
+                        false => self.flat(e),
+                    }
+                };
+                format!(
+                    "{} {} {}",
+                    side(left, Associativity::Left),
+                    op,
+                    side(right, Associativity::Right)
+                )
+            }
+            Astkind::Number(..) | Astkind::Float(_) => match self.literals.get(&e.loc.0) {
+                Some(text) => text.to_string(),
+                None => e.to_string(),
+            },
+            _ => e.to_string(),
+        }
+    }
+
+    // columnから書き始めて幅に収まらなければ、二項演算子の後ろで折り返す
+    // 折り返した行はindentより1段深く字下げする
+    fn layout(&self, e: &Ast, indent: usize, column: usize) -> String {
+        let flat = self.flat(e);
+        if column + flat.chars().count() <= self.style.width {
+            return flat;
+        }
+        match &e.value {
+            Astkind::Paren(inner) if self.style.preserve_parens => {
+                format!("({})", self.layout(inner, indent, column + 1))
+            }
+            Astkind::Paren(inner) => self.layout(inner, indent, column),
+            Astkind::UnaryOperation {
+                operation,
+                expression,
+            } => {
+                let child = self.child(expression);
+                let paren = unary_operand_needs_paren(child);
+                let op = operation.value.to_string();
+                let column = column + op.chars().count();
+                op + &self.layout_operand(child, paren, indent, column)
+            }
+            Astkind::BinaryOperation { .. } => {
+                let mut operands = Vec::new();
+                self.chain(e, &mut operands);
+                let next_indent = indent + self.style.indent;
+                let mut out = String::new();
+                for (op, operand, paren) in operands {
+                    match op {
+                        Some(op) => {
+                            out.push_str(&format!(" {}\n", op));
+                            out.push_str(&" ".repeat(next_indent));
+                            out.push_str(&self.layout_operand(
+                                operand,
+                                paren,
+                                next_indent,
+                                next_indent,
+                            ));
+                        }
+                        None => out.push_str(&self.layout_operand(operand, paren, indent, column)),
+                    }
+                }
+                out
+            }
+            _ => flat,
+        }
+    }
+
+    fn layout_operand(&self, e: &Ast, paren: bool, indent: usize, column: usize) -> String {
+        if paren {
+            format!("({})", self.layout(e, indent, column + 1))
+        } else {
+            self.layout(e, indent, column)
+        }
+    }
+
+    // 同じ優先順位の左結合の演算が続く部分を、演算子と被演算子の列にする
+    // 被演算子には括弧が必要かを添える。先頭の被演算子の演算子はNone
+    fn chain<'b>(
+        &self,
+        e: &'b Ast,
+        operands: &mut Vec<(Option<&'b BinaryOperationKind>, &'b Ast, bool)>,
+    ) {
+        if let Astkind::BinaryOperation {
+            operation,
+            left,
+            right,
+        } = &e.value
+        {
+            let op = &operation.value;
+            let (left, right) = (self.child(left), self.child(right));
+            match &left.value {
+                Astkind::BinaryOperation { operation: l, .. }
+                    if l.value.precedence() == op.precedence()
+                        && op.associativity() == Associativity::Left =>
+                {
+                    self.chain(left, operands)
+                }
+                _ => operands.push((
+                    None,
+                    left,
+                    binary_operand_needs_paren(op, left, Associativity::Left),
+                )),
+            }
+            operands.push((
+                Some(op),
+                right,
+                binary_operand_needs_paren(op, right, Associativity::Right),
+            ));
+        }
+    }
+}
+
+// 演算子のトークンの位置。二項演算子はxorを^にするなど、書き出す形も持つ
+#[derive(Default)]
+struct Operators {
+    binary: HashMap<usize, String>,
+    unary: HashSet<usize>,
+}
+
+impl Visitor for Operators {
+    fn visit_unary_operator(&mut self, operation: &UnaryOperation) {
+        self.unary.insert(operation.loc.0);
+    }
+
+    fn visit_binary_operator(&mut self, operation: &BinaryOperation) {
+        self.binary
+            .insert(operation.loc.0, operation.value.to_string());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format(input: &str) -> String {
+        format_source(input, &Style::default()).unwrap()
+    }
+
+    #[test]
+    fn test_format_keeps_literal_spelling() {
+        let input = "0xFF_FF+1_000_000*1e3-0.000001/1.50|0xab";
+        let expected = "0xFF_FF + 1_000_000 * 1e3 - 0.000001 / 1.50 | 0xab\n";
+        assert_eq!(format(input), expected);
+        // コメントを含む文や折り返した文でも同じ
+        assert_eq!(format("0xff_ff /* c */ +1.50"), "0xff_ff /* c */ + 1.50\n");
+        let narrow = Style {
+            width: 10,
+            ..Style::default()
+        };
+        assert_eq!(
+            format_source("1_000 + 0x0f + 2.50", &narrow).unwrap(),
+            "1_000 +\n    0x0f +\n    2.50\n"
+        );
+    }
+
+    #[test]
+    fn test_format_spacing() {
+        assert_eq!(format("1+2*  3"), "1 + 2 * 3\n");
+        assert_eq!(
+            format("let   x=(1+2)*-y\n\n\n\nx**2"),
+            "let x = (1 + 2) * -y\n\nx ** 2\n"
+        );
+        assert_eq!(format("a\r\nb *c\r\n"), "a\nb * c\n");
+        // 数値は書かれた綴りのまま
+        assert_eq!(
+            format("let x=0o17\n0xff+0b1010"),
+            "let x = 0o17\n0xff + 0b1010\n"
+        );
+        assert_eq!(format(""), "");
+        assert_eq!(format("\n\n"), "");
+    }
+
+    #[test]
+    fn test_format_parens() {
+        let minimal = Style {
+            preserve_parens: false,
+            ..Style::default()
+        };
+        assert_eq!(format("((1)) + (2 * 3)"), "((1)) + (2 * 3)\n");
+        assert_eq!(
+            format_source("((1)) + (2 * 3) - (4 - 5)", &minimal).unwrap(),
+            "1 + 2 * 3 - (4 - 5)\n"
+        );
+    }
+
+    #[test]
+    fn test_format_comments() {
+        let input = "# sizes\nlet kb=1<<10 # bytes\n/* mb */ let mb=kb*kb\n\n\n// end\n";
+        assert_eq!(
+            format(input),
+            "# sizes\nlet kb = 1 << 10 # bytes\n/* mb */ let mb = kb * kb\n\n// end\n"
+        );
+        // 被演算子の後ろの // は切り捨て除算で、演算子の後ろならコメント
+        assert_eq!(format("7//2"), "7 // 2\n");
+        assert_eq!(format("1 + // one\n  2"), "1 + // one\n    2\n");
+        // 文の途中のコメントは残したまま空白を整える
+        assert_eq!(format("1 /* one */ +2\n3+4"), "1 /* one */ + 2\n3 + 4\n");
+        assert_eq!(
+            format("let x=( /* a */ -1 xor 2)*y # t"),
+            "let x = (/* a */ -1 ^ 2) * y # t\n"
+        );
+        let formatted = format("let x = 1 + # one\n  2 /* two */ *\n\n  0x1f");
+        assert_eq!(formatted, "let x = 1 + # one\n    2 /* two */ * 0x1f\n");
+        assert!(is_formatted(&formatted, &Style::default()).unwrap());
+        assert!(!is_formatted("1 /* one */ +2\n", &Style::default()).unwrap());
+
+        let strip = Style {
+            preserve_comments: false,
+            ..Style::default()
+        };
+        assert_eq!(
+            format_source("1 /* one */ +2 # two\n", &strip).unwrap(),
+            "1 + 2\n"
+        );
+    }
+
+    #[test]
+    fn test_format_wrap() {
+        let style = Style {
+            width: 20,
+            indent: 2,
+            ..Style::default()
+        };
+        let input = "let total = alpha + beta * gamma - (delta + epsilon) * zeta";
+        let expected = "let total = alpha +\n  beta * gamma -\n  (delta + epsilon) *\n    zeta\n";
+        let formatted = format_source(input, &style).unwrap();
+        assert_eq!(formatted, expected);
+        // 整形した結果はもう一度整形しても変わらない
+        assert!(is_formatted(&formatted, &style).unwrap());
+        // 折り返した文も1つの文として読める
+        let source = SourceFile::new(&formatted);
+        let tokens = crate::lex(&formatted).unwrap();
+        assert_eq!(statement_ranges(&source, &tokens).len(), 1);
+    }
+
+    #[test]
+    fn test_format_error() {
+        assert!(matches!(
+            format_source("1 +\n", &Style::default()),
+            Err(Error::Parser(_))
+        ));
+        assert!(matches!(
+            format_source("1 $ 2", &Style::default()),
+            Err(Error::Lexer(_))
+        ));
+    }
+}
@@ -2,8 +2,21 @@ use crate::token::*;
 
 // 字句解析器
 pub fn lex(input: &str) -> Result<Vec<Token>, LexError> {
+    // エラーは入力の先頭から順に記録されるので、最初のものが従来のエラーにあたる
+    let (tokens, mut errors) = lex_recovering(input);
+    if errors.is_empty() {
+        Ok(tokens)
+    } else {
+        Err(errors.swap_remove(0))
+    }
+}
+
+// 不正な文字があっても最後まで字句解析し、壊れた部分をTokenKind::Errorにした
+// トークン列と見つかったエラーをすべて返す
+pub fn lex_recovering(input: &str) -> (Vec<Token>, Vec<LexError>) {
     // 解析結果を保存するベクタ
     let mut tokens = Vec::new();
+    let mut errors = Vec::new();
     // 入力
     let input = input.as_bytes();
     // 位置を管理する値
@@ -11,9 +24,19 @@ pub fn lex(input: &str) -> Result<Vec<Token>, LexError> {
     // サブレキサを呼んだ後posを更新するマクロ
     macro_rules! lex_a_token {
         ($lexer:expr) => {{
-            let (tok, p) = $lexer?;
-            tokens.push(tok);
-            position = p;
+            match $lexer {
+                Ok((tok, p)) => {
+                    tokens.push(tok);
+                    position = p;
+                }
+                // 失敗したトークンは読み飛ばしてエラートークンにする
+                Err(e) => {
+                    let p = skip_invalid_token(input, position, &e);
+                    tokens.push(Token::error(Location(position, p)));
+                    errors.push(e);
+                    position = p;
+                }
+            }
         }};
     }
     while position < input.len() {
@@ -23,25 +46,229 @@ pub fn lex(input: &str) -> Result<Vec<Token>, LexError> {
             b'0'..=b'9' => lex_a_token!(lex_number(input, position)),
             b'+' => lex_a_token!(lex_plus(input, position)),
             b'-' => lex_a_token!(lex_minus(input, position)),
+            // 2文字目まで見て ** か * かを決める
+            b'*' if input.get(position + 1) == Some(&b'*') => {
+                lex_a_token!(lex_double_asterisk(input, position))
+            }
             b'*' => lex_a_token!(lex_asterisk(input, position)),
+            // コメントは空白と同じく読み飛ばす
+            b'#' => {
+                let ((), p) = skip_line_comment(input, position).unwrap();
+                position = p;
+            }
+            b'/' if input.get(position + 1) == Some(&b'*') => {
+                match skip_block_comment(input, position) {
+                    Ok(((), p)) => position = p,
+                    Err(e) => {
+                        errors.push(e);
+                        position = input.len();
+                    }
+                }
+            }
+            // // は被演算子の後ろなら切り捨て除算の演算子、それ以外ではコメント
+            b'/' if input.get(position + 1) == Some(&b'/')
+                && starts_line_comment(input, position, tokens.last()) =>
+            {
+                let ((), p) = skip_line_comment(input, position).unwrap();
+                position = p;
+            }
+            b'/' if input.get(position + 1) == Some(&b'/') => {
+                lex_a_token!(lex_double_slash(input, position))
+            }
             b'/' => lex_a_token!(lex_slash(input, position)),
+            b'%' => lex_a_token!(lex_percent(input, position)),
+            b'&' => lex_a_token!(lex_ampersand(input, position)),
+            b'|' => lex_a_token!(lex_pipe(input, position)),
+            b'^' => lex_a_token!(lex_caret(input, position)),
+            b'~' => lex_a_token!(lex_tilde(input, position)),
+            // < と > は2文字でしか使わないので1文字目だけならエラーになる
+            b'<' => lex_a_token!(lex_double_less(input, position)),
+            b'>' => lex_a_token!(lex_double_greater(input, position)),
             b'(' => lex_a_token!(lex_lparen(input, position)),
             b')' => lex_a_token!(lex_rparen(input, position)),
-            // 空白を扱う
-            b' ' | b'\n' | b'\t' => {
-                let ((), p) = skip_spaces(input, position)?;
+            b'=' => lex_a_token!(lex_equal(input, position)),
+            b'a'..=b'z' | b'A'..=b'Z' | b'_' => lex_a_token!(lex_ident(input, position)),
+            // 空白を扱う。CRLFの改行の\rも空白とみなす
+            b' ' | b'\r' | b'\n' | b'\t' => {
+                let ((), p) = skip_spaces(input, position).unwrap();
                 position = p;
             }
-            // それ以外が来たらエラー
-            b => {
-                return Err(LexError::invalid_char(
-                    b as char,
-                    Location(position, position + 1),
-                ))
+            // それ以外が来たらエラー。続く不正な文字は1つのエラートークンにまとめる
+            _ => {
+                let start = position;
+                while position < input.len() && !is_token_start(input[position]) {
+                    // 多バイト文字は文字単位でまとめて報告する
+                    let (c, len) = decode_char(input, position);
+                    errors.push(LexError::invalid_char(
+                        c,
+                        Location(position, position + len),
+                    ));
+                    position += len;
+                }
+                tokens.push(Token::error(Location(start, position)));
             }
         }
     }
-    Ok(tokens)
+    (tokens, errors)
+}
+
+// 空白も捨てずに字句解析する。トークンの前の空白はそのトークンのトリビアにし、
+// 最後のトークンより後ろの空白は2つめの戻り値として返す
+pub fn lex_lossless(input: &str) -> (Vec<LosslessToken>, Vec<Trivia>, Vec<LexError>) {
+    let (tokens, errors) = lex_recovering(input);
+    let mut lossless = Vec::with_capacity(tokens.len());
+    // トークンの間の隙間がトリビアになる
+    let mut position = 0;
+    for token in tokens {
+        let leading = lex_trivia(input, position, token.loc.0);
+        position = token.loc.1;
+        lossless.push(LosslessToken {
+            leading,
+            text: token.loc.slice(input).to_string(),
+            token,
+        });
+    }
+    let trailing = lex_trivia(input, position, input.len());
+    (lossless, trailing, errors)
+}
+
+// トークンの間のstartからendまでを空白とコメントのトリビアに分ける
+fn lex_trivia(input: &str, start: usize, end: usize) -> Vec<Trivia> {
+    let bytes = &input.as_bytes()[..end];
+    let mut trivia = Vec::new();
+    let mut position = start;
+    while position < end {
+        let p = match bytes[position] {
+            b'#' => skip_line_comment(bytes, position).unwrap().1,
+            b'/' if bytes.get(position + 1) == Some(&b'/') => {
+                skip_line_comment(bytes, position).unwrap().1
+            }
+            // 閉じていないコメントは入力の終わりまで
+            b'/' => skip_block_comment(bytes, position).map_or(end, |((), p)| p),
+            _ => skip_spaces(bytes, position).unwrap().1,
+        };
+        let loc = Location(position, p);
+        let text = loc.slice(input);
+        trivia.push(match bytes[position] {
+            b'#' => Trivia::line_comment(text, loc),
+            b'/' if text.starts_with("//") => Trivia::line_comment(text, loc),
+            b'/' => Trivia::block_comment(text, loc),
+            _ => Trivia::whitespace(text, loc),
+        });
+        position = p;
+    }
+    trivia
+}
+
+// 文字の境界にあるpositionからUTF-8の1文字を読み、その文字とバイト数を返す
+// サブレキサはASCIIしか読まないので、位置は常に文字の境界にある
+fn decode_char(input: &[u8], position: usize) -> (char, usize) {
+    let len = match input[position] {
+        b if b < 0x80 => 1,
+        b if b >= 0xF0 => 4,
+        b if b >= 0xE0 => 3,
+        _ => 2,
+    };
+    let c = std::str::from_utf8(&input[position..position + len])
+        .unwrap()
+        .chars()
+        .next()
+        .unwrap();
+    (c, len)
+}
+
+// トークンか空白の始まりになれる文字か
+fn is_token_start(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b"_+-*/%&|^~<>()= \r\n\t#".contains(&b)
+}
+
+// サブレキサが失敗したとき、解析を再開する位置を決める
+fn skip_invalid_token(input: &[u8], position: usize, e: &LexError) -> usize {
+    if input[position].is_ascii_digit() {
+        // 数値は後ろに続く数字や英字もまとめて読み飛ばす
+        let p = recognize_many(input, position, |b| {
+            b.is_ascii_alphanumeric() || b == b'_' || b == b'.'
+        });
+        p.max(e.loc.1)
+    } else {
+        // 記号は最初の1文字だけ飛ばす。エラーが後ろの文字を指していたらその後ろまで飛ばし、
+        // 同じ文字を_の分岐でもう一度報告しないようにする
+        (position + 1).max(e.loc.1)
+    }
+}
+
+// 全角文字や組版用の記号をASCIIに置き換えてから字句解析する
+// トークンとエラーの位置は元の入力の文字を指し、トークンには元の綴りを、
+// エラーの文字には置き換える前の文字を入れる
+pub fn lex_normalized(input: &str) -> Result<Vec<NormalizedToken>, LexError> {
+    let (tokens, mut errors) = lex_normalized_recovering(input);
+    if errors.is_empty() {
+        Ok(tokens)
+    } else {
+        Err(errors.swap_remove(0))
+    }
+}
+
+pub fn lex_normalized_recovering(input: &str) -> (Vec<NormalizedToken>, Vec<LexError>) {
+    let (normalized, offsets) = normalize(input);
+    let (tokens, mut errors) = lex_recovering(&normalized);
+    // 置き換え後の位置を元の入力の位置に戻す
+    let restore = |loc: &Location| Location(offsets[loc.0], offsets[loc.1]);
+    let tokens = tokens
+        .into_iter()
+        .map(|tok| {
+            let loc = restore(&tok.loc);
+            NormalizedToken {
+                original: loc.slice(input).to_string(),
+                token: Token::new(tok.value, loc),
+            }
+        })
+        .collect();
+    for e in errors.iter_mut() {
+        e.loc = restore(&e.loc);
+        // エラーの文字は1文字分の位置を持つので、その位置の元の文字に戻す
+        let original = e.loc.slice(input).chars().next();
+        match (&mut e.value, original) {
+            (LexErrorKind::InvalidChar(c), Some(o)) | (LexErrorKind::InvalidDigit(c), Some(o)) => {
+                *c = o
+            }
+            _ => (),
+        }
+    }
+    (tokens, errors)
+}
+
+// IMEで入力されがちな文字に対応するASCII文字
+fn normalize_char(c: char) -> Option<char> {
+    match c {
+        // 全角数字
+        '０'..='９' => char::from_u32(c as u32 - '０' as u32 + '0' as u32),
+        '＋' => Some('+'),
+        // 全角ハイフンマイナスと数学のマイナス記号
+        '－' | '−' => Some('-'),
+        '×' | '＊' => Some('*'),
+        '÷' | '／' => Some('/'),
+        '（' => Some('('),
+        '）' => Some(')'),
+        // 全角スペース
+        '\u{3000}' => Some(' '),
+        _ => None,
+    }
+}
+
+// 置き換えた文字列と、置き換え後の各バイト位置が元の入力のどこにあたるかの表を返す
+fn normalize(input: &str) -> (String, Vec<usize>) {
+    let mut normalized = String::with_capacity(input.len());
+    let mut offsets = Vec::with_capacity(input.len() + 1);
+    for (i, c) in input.char_indices() {
+        let c = normalize_char(c).unwrap_or(c);
+        for _ in 0..c.len_utf8() {
+            offsets.push(i);
+        }
+        normalized.push(c);
+    }
+    offsets.push(input.len());
+    (normalized, offsets)
 }
 
 pub fn consume_byte(input: &[u8], position: usize, b: u8) -> Result<(u8, usize), LexError> {
@@ -49,9 +276,10 @@ pub fn consume_byte(input: &[u8], position: usize, b: u8) -> Result<(u8, usize),
         return Err(LexError::eof(Location(position, position)));
     }
     if input[position] != b {
+        let (c, len) = decode_char(input, position);
         return Err(LexError::invalid_char(
-            input[position] as char,
-            Location(position, position + 1),
+            c,
+            Location(position, position + len),
         ));
     }
 
@@ -70,10 +298,57 @@ fn lex_asterisk(input: &[u8], start: usize) -> Result<(Token, usize), LexError>
     consume_byte(input, start, b'*').map(|(_, end)| (Token::asterisk(Location(start, end)), end))
 }
 
+fn lex_double_asterisk(input: &[u8], start: usize) -> Result<(Token, usize), LexError> {
+    consume_byte(input, start, b'*')
+        .and_then(|(_, p)| consume_byte(input, p, b'*'))
+        .map(|(_, end)| (Token::double_asterisk(Location(start, end)), end))
+}
+
 fn lex_slash(input: &[u8], start: usize) -> Result<(Token, usize), LexError> {
     consume_byte(input, start, b'/').map(|(_, end)| (Token::slash(Location(start, end)), end))
 }
 
+fn lex_double_slash(input: &[u8], start: usize) -> Result<(Token, usize), LexError> {
+    consume_byte(input, start, b'/')
+        .and_then(|(_, p)| consume_byte(input, p, b'/'))
+        .map(|(_, end)| (Token::double_slash(Location(start, end)), end))
+}
+
+fn lex_percent(input: &[u8], start: usize) -> Result<(Token, usize), LexError> {
+    consume_byte(input, start, b'%').map(|(_, end)| (Token::percent(Location(start, end)), end))
+}
+
+fn lex_ampersand(input: &[u8], start: usize) -> Result<(Token, usize), LexError> {
+    consume_byte(input, start, b'&').map(|(_, end)| (Token::ampersand(Location(start, end)), end))
+}
+
+fn lex_pipe(input: &[u8], start: usize) -> Result<(Token, usize), LexError> {
+    consume_byte(input, start, b'|').map(|(_, end)| (Token::pipe(Location(start, end)), end))
+}
+
+fn lex_caret(input: &[u8], start: usize) -> Result<(Token, usize), LexError> {
+    consume_byte(input, start, b'^').map(|(_, end)| (Token::caret(Location(start, end)), end))
+}
+
+fn lex_tilde(input: &[u8], start: usize) -> Result<(Token, usize), LexError> {
+    consume_byte(input, start, b'~').map(|(_, end)| (Token::tilde(Location(start, end)), end))
+}
+
+// < と > は1文字だけなら、続く文字ではなくその < や > をエラーにする
+fn lex_double_less(input: &[u8], start: usize) -> Result<(Token, usize), LexError> {
+    consume_byte(input, start, b'<')
+        .and_then(|(_, p)| consume_byte(input, p, b'<'))
+        .map(|(_, end)| (Token::double_less(Location(start, end)), end))
+        .map_err(|_| LexError::invalid_char('<', Location(start, start + 1)))
+}
+
+fn lex_double_greater(input: &[u8], start: usize) -> Result<(Token, usize), LexError> {
+    consume_byte(input, start, b'>')
+        .and_then(|(_, p)| consume_byte(input, p, b'>'))
+        .map(|(_, end)| (Token::double_greater(Location(start, end)), end))
+        .map_err(|_| LexError::invalid_char('>', Location(start, start + 1)))
+}
+
 fn lex_lparen(input: &[u8], start: usize) -> Result<(Token, usize), LexError> {
     consume_byte(input, start, b'(').map(|(_, end)| (Token::lparen(Location(start, end)), end))
 }
@@ -82,21 +357,178 @@ fn lex_rparen(input: &[u8], start: usize) -> Result<(Token, usize), LexError> {
     consume_byte(input, start, b')').map(|(_, end)| (Token::rparen(Location(start, end)), end))
 }
 
+fn lex_equal(input: &[u8], start: usize) -> Result<(Token, usize), LexError> {
+    consume_byte(input, start, b'=').map(|(_, end)| (Token::equal(Location(start, end)), end))
+}
+
+// 識別子かキーワード
+fn lex_ident(input: &[u8], position: usize) -> Result<(Token, usize), LexError> {
+    use ::std::str::from_utf8;
+
+    let start = position;
+    let end = recognize_many(input, start, |b| b.is_ascii_alphanumeric() || b == b'_');
+    let loc = Location(start, end);
+    let token = match from_utf8(&input[start..end]).unwrap() {
+        "let" => Token::let_(loc),
+        "xor" => Token::xor(loc),
+        name => Token::ident(name, loc),
+    };
+    Ok((token, end))
+}
+
 fn lex_number(input: &[u8], position: usize) -> Result<(Token, usize), LexError> {
     use ::std::str::from_utf8;
 
+    // 0x, 0o, 0bで始まる場合は基数付きの整数
+    if input[position] == b'0' && position + 1 < input.len() {
+        match input[position + 1] {
+            b'x' | b'X' => return lex_radix_number(input, position, Radix::Hexadecimal),
+            b'o' | b'O' => return lex_radix_number(input, position, Radix::Octal),
+            b'b' | b'B' => return lex_radix_number(input, position, Radix::Binary),
+            _ => (),
+        }
+    }
+
+    let is_digit = |b| b"1234567890".contains(&b);
+    // 2文字目以降は区切りの_も許す
+    let is_digit_or_separator = |b| b"1234567890_".contains(&b);
     let start = position;
-    let end = recognize_many(input, start, |b| b"1234567890".contains(&b));
-    let n = from_utf8(&input[start..end]).unwrap().parse().unwrap();
-    Ok((Token::number(n, Location(start, end)), end))
+    let mut end = recognize_many(input, start, is_digit_or_separator);
+    let mut is_float = false;
+    // 小数部 ("." DIGITS)
+    if end < input.len() && input[end] == b'.' {
+        let p = end + 1;
+        if p >= input.len() || !is_digit(input[p]) {
+            return Err(LexError::malformed_number(Location(end, p)));
+        }
+        end = recognize_many(input, p, is_digit_or_separator);
+        is_float = true;
+    }
+    // 指数部 ("e"|"E") ("+"|"-")? DIGITS
+    if end < input.len() && (input[end] == b'e' || input[end] == b'E') {
+        let mut p = end + 1;
+        if p < input.len() && (input[p] == b'+' || input[p] == b'-') {
+            p += 1;
+        }
+        if p >= input.len() || !is_digit(input[p]) {
+            return Err(LexError::malformed_number(Location(end, p)));
+        }
+        end = recognize_many(input, p, is_digit_or_separator);
+        is_float = true;
+    }
+
+    let loc = Location(start, end);
+    let s = from_utf8(&input[start..end]).unwrap().replace('_', "");
+    if is_float {
+        // 書式は確認済みなので失敗するのは値が大きすぎて無限大になる場合のみ
+        let n: f64 = s.parse().unwrap();
+        if n.is_infinite() {
+            return Err(LexError::number_out_of_range(loc));
+        }
+        return Ok((Token::float(n, loc), end));
+    }
+    // 数字だけを読んでいるので失敗するのはu64に収まらない場合のみ
+    let n = s
+        .parse()
+        .map_err(|_| LexError::number_out_of_range(loc.clone()))?;
+    Ok((Token::number(n, loc), end))
+}
+
+fn lex_radix_number(
+    input: &[u8],
+    position: usize,
+    radix: Radix,
+) -> Result<(Token, usize), LexError> {
+    use ::std::str::from_utf8;
+
+    let start = position;
+    // 接頭辞の2文字を飛ばす
+    let digits_start = start + 2;
+    // 基数に合わない数字も一旦読んでからエラーにする
+    let end = recognize_many(input, digits_start, |b| {
+        b.is_ascii_alphanumeric() || b == b'_'
+    });
+    let digits = &input[digits_start..end];
+    if let Some(i) = digits
+        .iter()
+        .position(|&b| b != b'_' && !(b as char).is_digit(radix.value()))
+    {
+        let p = digits_start + i;
+        return Err(LexError::invalid_digit(
+            input[p] as char,
+            Location(p, p + 1),
+        ));
+    }
+    let s = from_utf8(digits).unwrap().replace('_', "");
+    if s.is_empty() {
+        return Err(LexError::missing_digits(Location(start, end)));
+    }
+
+    let loc = Location(start, end);
+    let n = u64::from_str_radix(&s, radix.value())
+        .map_err(|_| LexError::number_out_of_range(loc.clone()))?;
+    Ok((Token::number_with_radix(n, radix, loc), end))
 }
 
 // テストがループしていた理由は skip_spaces()の -- b" \n\t" -- が -- b"\n\t" -- になっていた。
 fn skip_spaces(input: &[u8], position: usize) -> Result<((), usize), LexError> {
-    let position = recognize_many(input, position, |b| b" \n\t".contains(&b));
+    let position = recognize_many(input, position, |b| b" \r\n\t".contains(&b));
     Ok(((), position))
 }
 
+// # か // から行末の手前まで読み飛ばす。CRLFの\rはコメントに含めない
+fn skip_line_comment(input: &[u8], start: usize) -> Result<((), usize), LexError> {
+    let mut position = recognize_many(input, start, |b| b != b'\n');
+    if position > start && input[position - 1] == b'\r' {
+        position -= 1;
+    }
+    Ok(((), position))
+}
+
+// /* から対応する */ の後ろまで読み飛ばす。/* */ は入れ子にできる
+fn skip_block_comment(input: &[u8], start: usize) -> Result<((), usize), LexError> {
+    let mut position = start + 2;
+    let mut depth = 1;
+    while position < input.len() {
+        match &input[position..] {
+            [b'/', b'*', ..] => {
+                depth += 1;
+                position += 2;
+            }
+            [b'*', b'/', ..] => {
+                depth -= 1;
+                position += 2;
+                if depth == 0 {
+                    return Ok(((), position));
+                }
+            }
+            _ => position += 1,
+        }
+    }
+    Err(LexError::unterminated_comment(Location(start, start + 2)))
+}
+
+// positionの // がコメントか。行頭か、直前のトークンが被演算子の終わりでなければコメント
+// 被演算子の後ろの // は切り捨て除算なので、そこにコメントを書くなら # か /* */ を使う
+fn starts_line_comment(input: &[u8], position: usize, prev: Option<&Token>) -> bool {
+    let after_operand = prev.is_some_and(|tok| {
+        matches!(
+            tok.value,
+            TokenKind::Number(..) | TokenKind::Float(_) | TokenKind::Ident(_) | TokenKind::Rparen
+        )
+    });
+    !after_operand || is_line_start(input, position)
+}
+
+// positionより前にその行に空白しかないか
+fn is_line_start(input: &[u8], position: usize) -> bool {
+    input[..position]
+        .iter()
+        .rev()
+        .take_while(|&&b| b != b'\n')
+        .all(|&b| b" \r\t".contains(&b))
+}
+
 fn recognize_many(input: &[u8], mut position: usize, mut f: impl FnMut(u8) -> bool) -> usize {
     while position < input.len() && f(input[position]) {
         position += 1;
@@ -193,6 +625,35 @@ mod tests {
         assert_eq!(expected_results, result);
     }
 
+    #[test]
+    fn test_lex_double_asterisk() {
+        let input = "2**3";
+        let test_input = input.as_bytes();
+        let test_position = 1;
+        let expected_results = Ok((
+            Annotation {
+                value: TokenKind::DoubleAsterisk,
+                loc: Location(1, 3),
+            },
+            test_position + 2,
+        ));
+        let result = lex_double_asterisk(test_input, test_position);
+        assert!(result.is_ok());
+        assert_eq!(expected_results, result);
+
+        assert_eq!(
+            lex("2 * *3 ** 4"),
+            Ok(vec![
+                Token::number(2, Location(0, 1)),
+                Token::asterisk(Location(2, 3)),
+                Token::asterisk(Location(4, 5)),
+                Token::number(3, Location(5, 6)),
+                Token::double_asterisk(Location(7, 9)),
+                Token::number(4, Location(10, 11)),
+            ])
+        );
+    }
+
     #[test]
     fn test_lex_slash() {
         let input = "123/";
@@ -210,6 +671,49 @@ mod tests {
         assert_eq!(expected_results, result);
     }
 
+    #[test]
+    fn test_lex_bitwise_operators() {
+        assert_eq!(
+            lex("7 // 2 % 3 & 1 | 2 ^ 3 xor ~4 << 1 >> 2"),
+            Ok(vec![
+                Token::number(7, Location(0, 1)),
+                Token::double_slash(Location(2, 4)),
+                Token::number(2, Location(5, 6)),
+                Token::percent(Location(7, 8)),
+                Token::number(3, Location(9, 10)),
+                Token::ampersand(Location(11, 12)),
+                Token::number(1, Location(13, 14)),
+                Token::pipe(Location(15, 16)),
+                Token::number(2, Location(17, 18)),
+                Token::caret(Location(19, 20)),
+                Token::number(3, Location(21, 22)),
+                Token::xor(Location(23, 26)),
+                Token::tilde(Location(27, 28)),
+                Token::number(4, Location(28, 29)),
+                Token::double_less(Location(30, 32)),
+                Token::number(1, Location(33, 34)),
+                Token::double_greater(Location(35, 37)),
+                Token::number(2, Location(38, 39)),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_lex_double_less() {
+        assert_eq!(
+            lex_double_less(b"1<<2", 1),
+            Ok((Token::double_less(Location(1, 3)), 3))
+        );
+        assert_eq!(
+            lex_double_less(b"1<2", 1),
+            Err(LexError::invalid_char('<', Location(1, 2)))
+        );
+        assert_eq!(
+            lex_double_greater(b"1>", 1),
+            Err(LexError::invalid_char('>', Location(1, 2)))
+        );
+    }
+
     #[test]
     fn test_lex_lparen() {
         let input = "123(";
@@ -244,6 +748,36 @@ mod tests {
         assert_eq!(expected_results, result);
     }
 
+    #[test]
+    fn test_lex_equal() {
+        let input = "x = 1";
+        let test_input = input.as_bytes();
+        let test_position = 2;
+        let expected_results = Ok((
+            Annotation {
+                value: TokenKind::Equal,
+                loc: Location(2, 3),
+            },
+            test_position + 1,
+        ));
+        let result = lex_equal(test_input, test_position);
+        assert!(result.is_ok());
+        assert_eq!(expected_results, result);
+    }
+
+    #[test]
+    fn test_lex_ident() {
+        assert_eq!(
+            lex_ident(b"foo_1 + 2", 0),
+            Ok((Token::ident("foo_1", Location(0, 5)), 5))
+        );
+        assert_eq!(lex_ident(b"let x", 0), Ok((Token::let_(Location(0, 3)), 3)));
+        assert_eq!(
+            lex_ident(b"letter", 0),
+            Ok((Token::ident("letter", Location(0, 6)), 6))
+        );
+    }
+
     #[test]
     fn test_lex_number() {
         let input = "1235()";
@@ -251,7 +785,7 @@ mod tests {
         let test_position = 0;
         let expected_results = Ok((
             Annotation {
-                value: TokenKind::Number(1235),
+                value: TokenKind::Number(1235, Radix::Decimal),
                 loc: Location(0, 4),
             },
             4,
@@ -261,6 +795,126 @@ mod tests {
         assert_eq!(expected_results, result);
     }
 
+    #[test]
+    fn test_lex_number_out_of_range() {
+        let input = "1 + 99999999999999999999999";
+        let test_input = input.as_bytes();
+        let test_position = 4;
+        let expected_results = Err(Annotation {
+            value: LexErrorKind::NumberOutOfRange,
+            loc: Location(4, 27),
+        });
+        let result = lex_number(test_input, test_position);
+        assert!(result.is_err());
+        assert_eq!(expected_results, result);
+
+        // u64::MAXちょうどは読める
+        let input = "18446744073709551615";
+        let result = lex_number(input.as_bytes(), 0);
+        assert_eq!(Ok((Token::number(u64::MAX, Location(0, 20)), 20)), result);
+    }
+
+    #[test]
+    fn test_lex_float() {
+        assert_eq!(
+            lex_number(b"2.75+", 0),
+            Ok((Token::float(2.75, Location(0, 4)), 4))
+        );
+        assert_eq!(
+            lex_number(b"6.02e23", 0),
+            Ok((Token::float(6.02e23, Location(0, 7)), 7))
+        );
+        assert_eq!(
+            lex_number(b"1E-3", 0),
+            Ok((Token::float(1e-3, Location(0, 4)), 4))
+        );
+        assert_eq!(
+            lex_number(b"1e999", 0),
+            Err(LexError::number_out_of_range(Location(0, 5)))
+        );
+    }
+
+    #[test]
+    fn test_lex_malformed_float() {
+        assert_eq!(
+            lex_number(b"1. + 2", 0),
+            Err(LexError::malformed_number(Location(1, 2)))
+        );
+        assert_eq!(
+            lex_number(b"1e", 0),
+            Err(LexError::malformed_number(Location(1, 2)))
+        );
+        assert_eq!(
+            lex_number(b"2.5e+*", 0),
+            Err(LexError::malformed_number(Location(3, 5)))
+        );
+    }
+
+    #[test]
+    fn test_lex_number_with_separators() {
+        assert_eq!(
+            lex_number(b"1_000_000", 0),
+            Ok((Token::number(1_000_000, Location(0, 9)), 9))
+        );
+        assert_eq!(
+            lex_number(b"1_0.2_5e1_0", 0),
+            Ok((Token::float(10.25e10, Location(0, 11)), 11))
+        );
+        assert_eq!(
+            lex_number(b"1._5", 0),
+            Err(LexError::malformed_number(Location(1, 2)))
+        );
+    }
+
+    #[test]
+    fn test_lex_radix_number() {
+        assert_eq!(
+            lex_number(b"0xFF_ff+", 0),
+            Ok((
+                Token::number_with_radix(0xFFFF, Radix::Hexadecimal, Location(0, 7)),
+                7
+            ))
+        );
+        assert_eq!(
+            lex_number(b"0o755", 0),
+            Ok((
+                Token::number_with_radix(0o755, Radix::Octal, Location(0, 5)),
+                5
+            ))
+        );
+        assert_eq!(
+            lex_number(b"0b1010_0101", 0),
+            Ok((
+                Token::number_with_radix(0b1010_0101, Radix::Binary, Location(0, 11)),
+                11
+            ))
+        );
+        assert_eq!(
+            lex_number(b"0x1_0000_0000_0000_0000", 0),
+            Err(LexError::number_out_of_range(Location(0, 23)))
+        );
+    }
+
+    #[test]
+    fn test_lex_radix_number_error() {
+        assert_eq!(
+            lex_number(b"0x + 1", 0),
+            Err(LexError::missing_digits(Location(0, 2)))
+        );
+        assert_eq!(
+            lex_number(b"0b__", 0),
+            Err(LexError::missing_digits(Location(0, 4)))
+        );
+        assert_eq!(
+            lex_number(b"0b1021", 0),
+            Err(LexError::invalid_digit('2', Location(4, 5)))
+        );
+        assert_eq!(
+            lex_number(b"0o8", 0),
+            Err(LexError::invalid_digit('8', Location(2, 3)))
+        );
+    }
+
     #[test]
     fn test_skip_spaces() {
         let input = " \n\t123";
@@ -272,6 +926,28 @@ mod tests {
         assert_eq!(expected_results, result);
     }
 
+    #[test]
+    fn test_lex_crlf() {
+        assert_eq!(
+            lex("1 +\r\n2 # two\r\n"),
+            Ok(vec![
+                Token::number(1, Location(0, 1)),
+                Token::plus(Location(2, 3)),
+                Token::number(2, Location(5, 6)),
+            ])
+        );
+        // 行コメントに改行の\rは含めない
+        let (_, trailing, _) = lex_lossless("1 # one\r\n");
+        assert_eq!(
+            trailing,
+            vec![
+                Trivia::whitespace(" ", Location(1, 2)),
+                Trivia::line_comment("# one", Location(2, 7)),
+                Trivia::whitespace("\r\n", Location(7, 9)),
+            ]
+        );
+    }
+
     #[test]
     fn test_recognize_many() {
         let input = "4789+++";
@@ -283,6 +959,158 @@ mod tests {
         assert_eq!(expected_result, result);
     }
 
+    #[test]
+    fn test_lex_recovering() {
+        let (tokens, errors) = lex_recovering("1 $$ + @2 0x * 3.e 0b12");
+        assert_eq!(
+            tokens,
+            vec![
+                Token::number(1, Location(0, 1)),
+                Token::error(Location(2, 4)),
+                Token::plus(Location(5, 6)),
+                Token::error(Location(7, 8)),
+                Token::number(2, Location(8, 9)),
+                Token::error(Location(10, 12)),
+                Token::asterisk(Location(13, 14)),
+                Token::error(Location(15, 18)),
+                Token::error(Location(19, 23)),
+            ]
+        );
+        assert_eq!(
+            errors,
+            vec![
+                LexError::invalid_char('$', Location(2, 3)),
+                LexError::invalid_char('$', Location(3, 4)),
+                LexError::invalid_char('@', Location(7, 8)),
+                LexError::missing_digits(Location(10, 12)),
+                LexError::malformed_number(Location(16, 17)),
+                LexError::invalid_digit('2', Location(22, 23)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lex_recovering_reports_each_char_once() {
+        let (_, errors) = lex_recovering("1 <ü <<ü >");
+        assert_eq!(
+            errors,
+            vec![
+                LexError::invalid_char('<', Location(2, 3)),
+                LexError::invalid_char('ü', Location(3, 5)),
+                LexError::invalid_char('ü', Location(8, 10)),
+                LexError::invalid_char('>', Location(11, 12)),
+            ]
+        );
+        // サブレキサが次の文字を指すエラーを返しても、その文字は1回だけ報告する
+        let e = LexError::invalid_char('ü', Location(3, 5));
+        assert_eq!(skip_invalid_token("1 <ü".as_bytes(), 2, &e), 5);
+    }
+
+    #[test]
+    fn test_lex_returns_first_error() {
+        assert_eq!(
+            lex("1 + $ + @"),
+            Err(LexError::invalid_char('$', Location(4, 5)))
+        );
+    }
+
+    #[test]
+    fn test_lex_multibyte() {
+        // éは2バイト、×は2バイト、😀は4バイト
+        let (tokens, errors) = lex_recovering("é1 × 2😀");
+        assert_eq!(
+            tokens,
+            vec![
+                Token::error(Location(0, 2)),
+                Token::number(1, Location(2, 3)),
+                Token::error(Location(4, 6)),
+                Token::number(2, Location(7, 8)),
+                Token::error(Location(8, 12)),
+            ]
+        );
+        assert_eq!(
+            errors,
+            vec![
+                LexError::invalid_char('é', Location(0, 2)),
+                LexError::invalid_char('×', Location(4, 6)),
+                LexError::invalid_char('😀', Location(8, 12)),
+            ]
+        );
+        assert_eq!(Location(8, 12).char_range("é1 × 2😀"), (6, 7));
+        // 連続する多バイト文字もそれぞれ文字の境界で区切る
+        assert_eq!(
+            lex_recovering("あい").1,
+            vec![
+                LexError::invalid_char('あ', Location(0, 3)),
+                LexError::invalid_char('い', Location(3, 6)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_consume_byte_multibyte() {
+        assert_eq!(
+            consume_byte("1 <ü".as_bytes(), 3, b'<'),
+            Err(LexError::invalid_char('ü', Location(3, 5)))
+        );
+    }
+
+    #[test]
+    fn test_lex_normalized() {
+        // 全角数字と記号は3バイト、×と÷は2バイト
+        let tokens: Vec<Token> = lex_normalized("１２ × （3 − ４）÷２")
+            .unwrap()
+            .into_iter()
+            .map(|tok| tok.token)
+            .collect();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::number(12, Location(0, 6)),
+                Token::asterisk(Location(7, 9)),
+                Token::lparen(Location(10, 13)),
+                Token::number(3, Location(13, 14)),
+                Token::minus(Location(15, 18)),
+                Token::number(4, Location(19, 22)),
+                Token::rparen(Location(22, 25)),
+                Token::slash(Location(25, 27)),
+                Token::number(2, Location(27, 30)),
+            ]
+        );
+        // トークンは元の綴りを持つ
+        let input = "１＋2−";
+        let tokens = lex_normalized(input).unwrap();
+        let spellings: Vec<&str> = tokens.iter().map(|tok| tok.original.as_str()).collect();
+        assert_eq!(spellings, vec!["１", "＋", "2", "−"]);
+        // 正規化しなければ従来どおりエラーになる
+        assert_eq!(
+            lex(input),
+            Err(LexError::invalid_char('１', Location(0, 3)))
+        );
+    }
+
+    #[test]
+    fn test_lex_normalized_error_location() {
+        // 全角の0xの後に数字がない
+        assert_eq!(
+            lex_normalized("１　＋　０x"),
+            Err(LexError::missing_digits(Location(12, 16)))
+        );
+        assert_eq!(
+            lex_normalized("１２＄"),
+            Err(LexError::invalid_char('＄', Location(6, 9)))
+        );
+        // 基数に合わない数字は置き換える前の文字で報告する
+        assert_eq!(
+            lex_normalized("0o８"),
+            Err(LexError::invalid_digit('８', Location(2, 5)))
+        );
+        assert_eq!(
+            lex_normalized_recovering("１ ＋ 0b１２").1,
+            vec![LexError::invalid_digit('２', Location(13, 16))]
+        );
+    }
+
     #[test]
     fn test_lexer() {
         assert_eq!(
@@ -297,6 +1125,144 @@ mod tests {
                 Token::minus(Location(12, 13)),
                 Token::number(10, Location(14, 16)),
             ])
+        );
+        assert_eq!(
+            lex("let x = y * 2"),
+            Ok(vec![
+                Token::let_(Location(0, 3)),
+                Token::ident("x", Location(4, 5)),
+                Token::equal(Location(6, 7)),
+                Token::ident("y", Location(8, 9)),
+                Token::asterisk(Location(10, 11)),
+                Token::number(2, Location(12, 13)),
+            ])
         )
     }
+
+    #[test]
+    fn test_lex_lossless() {
+        let input = " 1 +\n\t$ ";
+        let (tokens, trailing, errors) = lex_lossless(input);
+        assert_eq!(
+            tokens,
+            vec![
+                LosslessToken {
+                    leading: vec![Trivia::whitespace(" ", Location(0, 1))],
+                    token: Token::number(1, Location(1, 2)),
+                    text: "1".to_string(),
+                },
+                LosslessToken {
+                    leading: vec![Trivia::whitespace(" ", Location(2, 3))],
+                    token: Token::plus(Location(3, 4)),
+                    text: "+".to_string(),
+                },
+                LosslessToken {
+                    leading: vec![Trivia::whitespace("\n\t", Location(4, 6))],
+                    token: Token::error(Location(6, 7)),
+                    text: "$".to_string(),
+                },
+            ]
+        );
+        assert_eq!(trailing, vec![Trivia::whitespace(" ", Location(7, 8))]);
+        assert_eq!(errors, vec![LexError::invalid_char('$', Location(6, 7))]);
+
+        let rebuilt: String = tokens.iter().map(|t| t.to_string()).collect();
+        assert_eq!(rebuilt + trailing[0].text(), input);
+    }
+
+    #[test]
+    fn test_lex_comments() {
+        assert_eq!(
+            lex("1 # one\n+ /* two /* nested */ */ 2"),
+            Ok(vec![
+                Token::number(1, Location(0, 1)),
+                Token::plus(Location(8, 9)),
+                Token::number(2, Location(33, 34)),
+            ])
+        );
+        // 行頭の // はコメントで、それ以外は切り捨て除算
+        assert_eq!(
+            lex("  // note\n7 // 2"),
+            Ok(vec![
+                Token::number(7, Location(10, 11)),
+                Token::double_slash(Location(12, 14)),
+                Token::number(2, Location(15, 16)),
+            ])
+        );
+        // 被演算子の後ろでは行の途中でも除算になり、演算子の後ろならコメントになる
+        assert_eq!(
+            lex("5 // five"),
+            Ok(vec![
+                Token::number(5, Location(0, 1)),
+                Token::double_slash(Location(2, 4)),
+                Token::ident("five", Location(5, 9)),
+            ])
+        );
+        assert_eq!(
+            lex("1 + // one\n2 (x) // 2"),
+            Ok(vec![
+                Token::number(1, Location(0, 1)),
+                Token::plus(Location(2, 3)),
+                Token::number(2, Location(11, 12)),
+                Token::lparen(Location(13, 14)),
+                Token::ident("x", Location(14, 15)),
+                Token::rparen(Location(15, 16)),
+                Token::double_slash(Location(17, 19)),
+                Token::number(2, Location(20, 21)),
+            ])
+        );
+        assert_eq!(lex("# only a comment"), Ok(vec![]));
+    }
+
+    #[test]
+    fn test_lex_unterminated_comment() {
+        assert_eq!(
+            lex("1 + /* a /* b */ 2"),
+            Err(LexError::unterminated_comment(Location(4, 6)))
+        );
+        let (tokens, errors) = lex_recovering("1 /* 2");
+        assert_eq!(tokens, vec![Token::number(1, Location(0, 1))]);
+        assert_eq!(errors, vec![LexError::unterminated_comment(Location(2, 4))]);
+    }
+
+    #[test]
+    fn test_lex_lossless_comments() {
+        let input = "// head\n1 /* a */ # tail\n/* open";
+        let (tokens, trailing, _) = lex_lossless(input);
+        assert_eq!(
+            tokens[0].leading,
+            vec![
+                Trivia::line_comment("// head", Location(0, 7)),
+                Trivia::whitespace("\n", Location(7, 8)),
+            ]
+        );
+        assert_eq!(
+            trailing,
+            vec![
+                Trivia::whitespace(" ", Location(9, 10)),
+                Trivia::block_comment("/* a */", Location(10, 17)),
+                Trivia::whitespace(" ", Location(17, 18)),
+                Trivia::line_comment("# tail", Location(18, 24)),
+                Trivia::whitespace("\n", Location(24, 25)),
+                Trivia::block_comment("/* open", Location(25, 32)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lex_lossless_hash_asterisk() {
+        // #の直後の*もコメントの中身で、ブロックコメントの始まりではない
+        let input = "#*** header ***\n1";
+        let (tokens, trailing, errors) = lex_lossless(input);
+        assert_eq!(errors, vec![]);
+        assert_eq!(trailing, vec![]);
+        assert_eq!(
+            tokens[0].leading,
+            vec![
+                Trivia::line_comment("#*** header ***", Location(0, 15)),
+                Trivia::whitespace("\n", Location(15, 16)),
+            ]
+        );
+        assert_eq!(crate::SyntaxTree::parse(input).0.to_string(), input);
+    }
 }
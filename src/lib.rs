@@ -0,0 +1,174 @@
+pub mod ast;
+pub mod cst;
+pub mod diagnostics;
+pub mod eval;
+pub mod formatter;
+pub mod lexer;
+pub mod optimizer;
+pub mod parser;
+pub mod source;
+pub mod token;
+
+pub use ast::{Ast, ParseError, Statement, Structural, StructuralEq};
+pub use cst::{Cst, CstStatement, SyntaxTree};
+pub use eval::{eval, Environment, EvalError, Value};
+pub use lexer::{lex, lex_lossless, lex_normalized, lex_normalized_recovering, lex_recovering};
+pub use optimizer::{optimize, optimize_statement};
+pub use parser::{parse, parse_recovering, parse_statement, OperatorTable, Parser};
+pub use source::{FileId, LineColumn, SourceDatabase, SourceFile, Span};
+pub use token::{LexError, Location, Token};
+
+use diagnostics::Diagnostic;
+use std::fmt;
+use token::TokenKind;
+
+// 字句解析、パース、評価のいずれかで起きたエラー
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Error {
+    Lexer(LexError),
+    Parser(ParseError),
+    Eval(EvalError),
+}
+
+impl From<LexError> for Error {
+    fn from(e: LexError) -> Self {
+        Error::Lexer(e)
+    }
+}
+
+impl From<ParseError> for Error {
+    fn from(e: ParseError) -> Self {
+        Error::Parser(e)
+    }
+}
+
+impl From<EvalError> for Error {
+    fn from(e: EvalError) -> Self {
+        Error::Eval(e)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Lexer(e) => e.fmt(f),
+            Error::Parser(e) => e.fmt(f),
+            Error::Eval(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Lexer(e) => Some(e),
+            Error::Parser(e) => Some(e),
+            Error::Eval(e) => Some(e),
+        }
+    }
+}
+
+impl Diagnostic for Error {
+    fn location(&self) -> Option<&Location> {
+        match self {
+            Error::Lexer(e) => e.location(),
+            Error::Parser(e) => e.location(),
+            Error::Eval(e) => e.location(),
+        }
+    }
+
+    fn message(&self) -> String {
+        match self {
+            Error::Lexer(e) => e.message(),
+            Error::Parser(e) => e.message(),
+            Error::Eval(e) => e.message(),
+        }
+    }
+}
+
+// どのファイルで起きたかを含むエラー
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SourceError {
+    pub file: FileId,
+    pub error: Error,
+}
+
+impl SourceError {
+    // 入力の終端を指すエラーはNone
+    pub fn span(&self) -> Option<Span> {
+        self.error.location().map(|loc| Span::new(self.file, loc))
+    }
+
+    pub fn render(&self, db: &SourceDatabase) -> String {
+        diagnostics::render_in_database(db, self.file, &self.error)
+    }
+}
+
+// 文字列を字句解析してからパースする
+pub fn parse_str(input: &str) -> Result<Ast, Error> {
+    let tokens = lex(input)?;
+    let ast = parse(tokens)?;
+    Ok(ast)
+}
+
+// 文字列を字句解析してから文としてパースする
+pub fn parse_statement_str(input: &str) -> Result<Statement, Error> {
+    let tokens = lex(input)?;
+    let statement = parse_statement(tokens)?;
+    Ok(statement)
+}
+
+// トークン列を文ごとに分け、それぞれの文にあたる添字の範囲を返す
+// 文は行ごとに区切るが、行が中置演算子や=で終わるときと括弧が閉じていないときは次の行に続く
+pub fn statement_ranges(source: &SourceFile, tokens: &[Token]) -> Vec<std::ops::Range<usize>> {
+    let table = OperatorTable::standard();
+    let mut ranges: Vec<std::ops::Range<usize>> = Vec::new();
+    let mut depth = 0usize;
+    let mut prev_line = None;
+    for (i, token) in tokens.iter().enumerate() {
+        let line = source.line_index(token.loc.0);
+        let continues = match i.checked_sub(1).map(|p| &tokens[p]) {
+            Some(prev) => {
+                prev_line == Some(line)
+                    || depth > 0
+                    || prev.value == TokenKind::Equal
+                    || table.get_infix(&prev.value).is_some()
+            }
+            None => false,
+        };
+        match ranges.last_mut() {
+            Some(range) if continues => range.end = i + 1,
+            _ => {
+                depth = 0;
+                ranges.push(i..i + 1);
+            }
+        }
+        match token.value {
+            TokenKind::Lparen => depth += 1,
+            TokenKind::Rparen => depth = depth.saturating_sub(1),
+            _ => (),
+        }
+        prev_line = Some(source.line_index(token.loc.1));
+    }
+    ranges
+}
+
+// ファイルの文を順に評価し、最後の文の値を返す。空のファイルならNone
+// 変数はenvに残るので、定数のファイルを先に評価しておけば後のファイルから参照できる
+pub fn eval_file(
+    db: &SourceDatabase,
+    file: FileId,
+    env: &mut Environment,
+) -> Result<Option<Value>, SourceError> {
+    let source = db.file(file);
+    let wrap = |error: Error| SourceError { file, error };
+    let tokens = lex(source.text()).map_err(|e| wrap(e.into()))?;
+
+    let mut ret = None;
+    for range in statement_ranges(source, &tokens) {
+        let statement = parse_statement(tokens[range].to_vec()).map_err(|e| wrap(e.into()))?;
+        let value = env.eval_statement(&statement).map_err(|e| wrap(e.into()))?;
+        ret = Some(value);
+    }
+    Ok(ret)
+}
@@ -0,0 +1,412 @@
+use crate::ast::{
+    Ast, Astkind, BinaryOperation, BinaryOperationKind, Fold, Statement, StatementKind,
+    UnaryOperation, UnaryOperationKind,
+};
+use crate::eval::{eval, Value};
+use crate::token::{Annotation, Location};
+
+// 式を評価結果を変えずに簡単にする
+//
+// - 定数だけの部分式を計算する。評価がエラーになる部分式はそのまま残すので、
+//   エラーは元と同じ位置で起きる
+// - x * 1, x + 0 などの恒等式と - -x, ~~x を取り除く。変数は未定義かもしれず、
+//   整数か小数かもわからないので、エラーや型が変わりうるものは取り除かない。
+//   x * 0 はxのエラーを消してしまうので簡単にしない
+// - & | ^ の定数は (x & 12) & 10 を x & 8 のようにまとめる
+//
+// 残った節点の位置は元の入力を指したままになる
+pub fn optimize(ast: Ast) -> Ast {
+    Optimizer.fold(ast)
+}
+
+pub fn optimize_statement(statement: Statement) -> Statement {
+    let Annotation { value, loc } = statement;
+    let value = match value {
+        StatementKind::Let { name, expression } => StatementKind::Let {
+            name,
+            expression: optimize(expression),
+        },
+        StatementKind::Expression(e) => StatementKind::Expression(optimize(e)),
+    };
+    Statement::new(value, loc)
+}
+
+struct Optimizer;
+
+impl Fold for Optimizer {
+    fn fold_paren(&mut self, expression: Ast, loc: Location) -> Ast {
+        let expression = self.fold(expression);
+        if is_literal(&expression) {
+            // 括弧の中が定数になったら括弧も含めた位置の定数にする
+            Ast::new(expression.value, loc)
+        } else {
+            Ast::paren(expression, loc)
+        }
+    }
+
+    fn fold_unary_operation(
+        &mut self,
+        operation: UnaryOperation,
+        expression: Ast,
+        loc: Location,
+    ) -> Ast {
+        let expression = self.fold(expression);
+        let node = Ast::unary_operation(operation, expression, loc);
+        match fold_constant(&node) {
+            Some(n) => n,
+            None => simplify_unary(node),
+        }
+    }
+
+    fn fold_binary_operation(
+        &mut self,
+        operation: BinaryOperation,
+        left: Ast,
+        right: Ast,
+        loc: Location,
+    ) -> Ast {
+        let left = self.fold(left);
+        let right = self.fold(right);
+        let node = Ast::binary_operation(operation, left, right, loc);
+        match fold_constant(&node) {
+            Some(n) => n,
+            None => simplify_binary(node),
+        }
+    }
+}
+
+// 評価した結果が整数か小数か。変数を含むなどでわからなければNone
+// エラーになる場合は考えず、値が得られたときの型を返す
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Type {
+    Int,
+    Float,
+}
+
+fn static_type(ast: &Ast) -> Option<Type> {
+    use crate::ast::BinaryOperationKind::*;
+    match &ast.value {
+        Astkind::Number(..) => Some(Type::Int),
+        Astkind::Float(_) => Some(Type::Float),
+        Astkind::Variable(_) | Astkind::Error => None,
+        Astkind::Paren(e) => static_type(e),
+        Astkind::UnaryOperation {
+            operation,
+            expression,
+        } => match operation.value {
+            UnaryOperationKind::BitNot => Some(Type::Int),
+            _ => static_type(expression),
+        },
+        Astkind::BinaryOperation {
+            operation,
+            left,
+            right,
+        } => match operation.value {
+            BitAnd | BitOr | BitXor | Shl | Shr => Some(Type::Int),
+            _ => match (static_type(left), static_type(right)) {
+                (Some(Type::Float), _) | (_, Some(Type::Float)) => Some(Type::Float),
+                (Some(Type::Int), Some(Type::Int)) => Some(Type::Int),
+                _ => None,
+            },
+        },
+    }
+}
+
+fn is_literal(ast: &Ast) -> bool {
+    matches!(ast.value, Astkind::Number(..) | Astkind::Float(_))
+}
+
+fn is_int(ast: &Ast, n: u64) -> bool {
+    matches!(ast.value, Astkind::Number(m, _) if m == n)
+}
+
+// 子がすべて定数なら計算して、節点の位置の定数にする。エラーになるならNone
+fn fold_constant(node: &Ast) -> Option<Ast> {
+    let constant_children = match &node.value {
+        Astkind::UnaryOperation { expression, .. } => is_literal(expression),
+        Astkind::BinaryOperation { left, right, .. } => is_literal(left) && is_literal(right),
+        _ => false,
+    };
+    if !constant_children {
+        return None;
+    }
+    match eval(node).ok()? {
+        Value::Int(n) => Some(Ast::number(n, node.loc.clone())),
+        Value::Float(n) => Some(Ast::float(n, node.loc.clone())),
+    }
+}
+
+fn simplify_unary(node: Ast) -> Ast {
+    let Annotation { value, loc } = node;
+    let (operation, expression) = match value {
+        Astkind::UnaryOperation {
+            operation,
+            expression,
+        } => (operation, *expression),
+        value => return Ast::new(value, loc),
+    };
+    match (&operation.value, &expression.without_parens().value) {
+        // +xはxをそのまま返す
+        (UnaryOperationKind::Plus, _) => expression,
+        // 符号なし整数の -x はエラーになり、小数の ~x もエラーになるので、
+        // 内側の演算がエラーにならない型のときだけ打ち消す
+        (
+            UnaryOperationKind::Minus,
+            Astkind::UnaryOperation {
+                operation: inner,
+                expression: x,
+            },
+        ) if inner.value == UnaryOperationKind::Minus && static_type(x) == Some(Type::Float) => {
+            (**x).clone()
+        }
+        (
+            UnaryOperationKind::BitNot,
+            Astkind::UnaryOperation {
+                operation: inner,
+                expression: x,
+            },
+        ) if inner.value == UnaryOperationKind::BitNot && static_type(x) == Some(Type::Int) => {
+            (**x).clone()
+        }
+        _ => Ast::unary_operation(operation, expression, loc),
+    }
+}
+
+fn simplify_binary(node: Ast) -> Ast {
+    use crate::ast::BinaryOperationKind::*;
+    let Annotation { value, loc } = node;
+    let (operation, left, right) = match value {
+        Astkind::BinaryOperation {
+            operation,
+            left,
+            right,
+        } => (operation, *left, *right),
+        value => return Ast::new(value, loc),
+    };
+    // ビット演算の恒等式は、小数のときのエラーを消さないよう整数とわかるときだけ使う
+    // 足し算と引き算の恒等式も、-0.0 + 0 が 0.0 になるので整数とわかるときだけ使う
+    let left_int = static_type(&left) == Some(Type::Int);
+    let right_int = static_type(&right) == Some(Type::Int);
+    match operation.value {
+        Add | Sub if is_int(&right, 0) && left_int => return left,
+        Add if is_int(&left, 0) && right_int => return right,
+        Mult if is_int(&right, 1) => return left,
+        Mult if is_int(&left, 1) => return right,
+        Div | Pow if is_int(&right, 1) => return left,
+        BitOr | BitXor | Shl | Shr if is_int(&right, 0) && left_int => return left,
+        BitOr | BitXor if is_int(&left, 0) && right_int => return right,
+        BitAnd if is_int(&right, u64::MAX) && left_int => return left,
+        BitAnd if is_int(&left, u64::MAX) && right_int => return right,
+        BitAnd | BitOr | BitXor => return reassociate(operation, left, right, loc),
+        _ => (),
+    }
+    Ast::binary_operation(operation, left, right, loc)
+}
+
+// (x op c1) op c2 を x op (c1 op c2) にする。opは結合的かつ可換なビット演算
+// 元の式でエラーになりうるのは内側の節点なので、まとめた節点には内側の位置を使う
+fn reassociate(operation: BinaryOperation, left: Ast, right: Ast, loc: Location) -> Ast {
+    let found = match (&left.without_parens().value, &right.value) {
+        (
+            Astkind::BinaryOperation {
+                operation: inner,
+                left: a,
+                right: b,
+            },
+            Astkind::Number(c2, _),
+        ) if inner.value == operation.value => match (&a.value, &b.value) {
+            (_, Astkind::Number(c1, radix)) => {
+                Some((inner.clone(), (**a).clone(), *c1, *radix, *c2))
+            }
+            (Astkind::Number(c1, radix), _) => {
+                Some((inner.clone(), (**b).clone(), *c1, *radix, *c2))
+            }
+            _ => None,
+        },
+        _ => None,
+    };
+    match found {
+        Some((inner, x, c1, radix, c2)) => {
+            let c = match operation.value {
+                BinaryOperationKind::BitAnd => c1 & c2,
+                BinaryOperationKind::BitOr => c1 | c2,
+                _ => c1 ^ c2,
+            };
+            // まとめた定数は内側の定数と同じ基数で書く
+            let inner_loc = left.without_parens().loc.clone();
+            let constant = Ast::number_with_radix(c, radix, right.loc);
+            Ast::binary_operation(inner, x, constant, inner_loc)
+        }
+        None => Ast::binary_operation(operation, left, right, loc),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::StructuralEq;
+    use crate::eval::Environment;
+    use crate::parse_str;
+
+    fn optimize_str(input: &str) -> Ast {
+        optimize(parse_str(input).unwrap())
+    }
+
+    // xが未定義、整数、小数のそれぞれで、最適化の前後の評価結果がエラーの位置まで一致する
+    // -0.0と0.0はValueとしては等しいので、出力の違いがわかるようDebugの形で比べる
+    fn assert_same_semantics(input: &str) {
+        let ast = parse_str(input).unwrap();
+        let optimized = optimize(ast.clone());
+        let mut envs = vec![Environment::new()];
+        for x in [
+            Value::Int(0),
+            Value::Int(6),
+            Value::Int(u64::MAX),
+            Value::Float(2.5),
+            Value::Float(-0.0),
+        ] {
+            let mut env = Environment::new();
+            env.set("x", x);
+            envs.push(env);
+        }
+        for env in envs {
+            assert_eq!(
+                format!("{:?}", env.eval(&ast)),
+                format!("{:?}", env.eval(&optimized)),
+                "{} -> {}",
+                input,
+                optimized
+            );
+        }
+    }
+
+    #[test]
+    fn test_fold_constants() {
+        assert_eq!(optimize_str("1 + 2 * 3"), Ast::number(7, Location(0, 9)));
+        assert_eq!(optimize_str("1 / 4.0"), Ast::float(0.25, Location(0, 7)));
+        // 括弧ごと定数になり、位置は括弧を含む
+        assert_eq!(
+            optimize_str("x * (2 + 3)"),
+            Ast::binary_operation(
+                BinaryOperation::mult(Location(2, 3)),
+                Ast::variable("x", Location(0, 1)),
+                Ast::number(5, Location(4, 11)),
+                Location(0, 11),
+            )
+        );
+    }
+
+    #[test]
+    fn test_keep_errors() {
+        // エラーになる部分式は計算せずに残す
+        for input in [
+            "x + 1 / 0",
+            "2 - 3",
+            "-1",
+            "~1.5",
+            "2 ** 64 * x",
+            "(1 << 64) | x",
+        ] {
+            let ast = parse_str(input).unwrap();
+            assert!(optimize(ast.clone()).structural_eq(&ast), "{}", input);
+        }
+        let ast = optimize_str("x + (4 - 5)");
+        let mut env = Environment::new();
+        env.set("x", Value::Int(1));
+        assert_eq!(
+            env.eval(&ast),
+            Err(crate::EvalError::negative_value(Location(5, 10)))
+        );
+    }
+
+    #[test]
+    fn test_identities() {
+        assert_eq!(optimize_str("x * 1"), Ast::variable("x", Location(0, 1)));
+        assert_eq!(
+            optimize_str("(1 * x) / 1"),
+            Ast::paren(Ast::variable("x", Location(5, 6)), Location(0, 7))
+        );
+        assert_eq!(optimize_str("+x ** 1"), Ast::variable("x", Location(1, 2)));
+        assert_eq!(optimize_str("0 + (x & 7) * 1 - 0").to_string(), "x & 7");
+        // xが小数や未定義ならエラーが消えたり結果が変わったりするので残す
+        // -0.0 + 0 は 0.0 になる
+        for input in [
+            "x * 0", "x | 0", "- -x", "~~x", "x // 1", "x * 1.0", "x + 0", "0 + x", "x - 0",
+        ] {
+            let ast = parse_str(input).unwrap();
+            assert!(optimize(ast.clone()).structural_eq(&ast), "{}", input);
+        }
+        // 型がわかれば打ち消せる
+        assert_eq!(optimize_str("- -(x * 1.5)").to_string(), "x * 1.5");
+        assert_eq!(optimize_str("~~(x & 3) | 0").to_string(), "x & 3");
+    }
+
+    #[test]
+    fn test_reassociate() {
+        assert_eq!(
+            optimize_str("(x & 12) & 10"),
+            Ast::binary_operation(
+                BinaryOperation::bit_and(Location(3, 4)),
+                Ast::variable("x", Location(1, 2)),
+                Ast::number(8, Location(11, 13)),
+                Location(1, 7),
+            )
+        );
+        assert_eq!(optimize_str("3 ^ x ^ 5").to_string(), "x ^ 6");
+        assert_eq!(optimize_str("(x | 1) & 2").to_string(), "(x | 1) & 2");
+    }
+
+    #[test]
+    fn test_same_semantics() {
+        for input in [
+            "x * 1 + 0",
+            "0 + x - 0",
+            "(x & 12) & 10",
+            "3 ^ x ^ 5 | 0",
+            "- -(x * 1.5)",
+            "~~(x & 3)",
+            "x + 1 / 0",
+            "x * (2 ** 3 - 8)",
+            "(x + 0) - 0 ** 1",
+            "x << 0 >> 0",
+            "-(x / 1)",
+            "y + x * 1",
+        ] {
+            assert_same_semantics(input);
+        }
+    }
+
+    #[test]
+    fn test_print_negative_float() {
+        // (-2.5) は負の小数の定数になるが、印字すると括弧が戻り同じ式として読み直せる
+        let optimized = optimize_str("(-2.5) ** x");
+        assert_eq!(optimized.to_string(), "(-2.5) ** x");
+        assert_eq!(optimize_str("(-2.5) * x").to_string(), "-2.5 * x");
+        for input in ["(-2.5) ** x", "(-0.0) ** x", "(-1.5 * 2) ** x ** 2"] {
+            let optimized = optimize_str(input);
+            let reparsed = parse_str(&optimized.to_string()).unwrap();
+            let mut env = Environment::new();
+            env.set("x", Value::Float(0.5));
+            let kind = |ast: &Ast| env.eval(ast).map_err(|e| e.value);
+            assert_eq!(
+                kind(&reparsed),
+                kind(&parse_str(input).unwrap()),
+                "{}",
+                input
+            );
+        }
+        let mut env = Environment::new();
+        env.set("x", Value::Float(0.5));
+        let reparsed = parse_str(&optimize_str("(-2.5) ** x").to_string()).unwrap();
+        assert_eq!(
+            env.eval(&reparsed).map_err(|e| e.value),
+            Err(crate::eval::EvalErrorKind::Undefined)
+        );
+    }
+
+    #[test]
+    fn test_optimize_statement() {
+        let statement = crate::parse_statement_str("let y = x * (1 + 1)").unwrap();
+        assert_eq!(optimize_statement(statement).to_string(), "let y = x * 2");
+    }
+}
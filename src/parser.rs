@@ -1,159 +1,404 @@
-use crate::ast::{Ast, Astkind, BinaryOperation, ParseError, UnaryOperation};
-use crate::token::{Token, TokenKind};
+pub use crate::ast::Associativity;
+use crate::ast::{
+    Ast, Astkind, BinaryOperation, BinaryOperationKind, ParseError, Statement, UnaryOperation,
+    UnaryOperationKind, PREFIX_PRECEDENCE,
+};
+use crate::token::{Location, Token, TokenKind};
 
+use std::collections::HashMap;
 use std::iter::Peekable;
 
-pub fn parse(tokens: Vec<Token>) -> Result<Ast, ParseError> {
-    // 入力をイテレーターにし、、Peekableにする
-    let mut tokens = tokens.into_iter().peekable();
-    // その後parse_exprをよんでエラー処理をする
-    let ret = parse_expr(&mut tokens)?;
-    match tokens.next() {
-        Some(token) => Err(ParseError::RedundantExpression(token)),
-        None => Ok(ret),
+// 前置・後置演算子の定義
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct UnaryOperator {
+    // 結合の強さ。大きいほど強く結合する
+    pub precedence: u8,
+    pub operation: UnaryOperationKind,
+}
+
+// 中置演算子の定義
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct InfixOperator {
+    pub precedence: u8,
+    pub associativity: Associativity,
+    pub operation: BinaryOperationKind,
+}
+
+// どのトークンをどの位置の演算子として扱うかの表
+#[derive(Debug, Clone, Default)]
+pub struct OperatorTable {
+    prefix: HashMap<TokenKind, UnaryOperator>,
+    infix: HashMap<TokenKind, InfixOperator>,
+    postfix: HashMap<TokenKind, UnaryOperator>,
+}
+
+impl OperatorTable {
+    // 演算子を1つも持たない表
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // 標準の演算子表。優先順位はCに合わせ、**だけはそれより強い右結合にしている
+    // 優先順位と結合性はASTの印字と共通のBinaryOperationKind::precedenceなどを使う
+    pub fn standard() -> Self {
+        use crate::ast::BinaryOperationKind::*;
+
+        let infix = [
+            (TokenKind::Pipe, BitOr),
+            (TokenKind::Caret, BitXor),
+            (TokenKind::Xor, BitXor),
+            (TokenKind::Ampersand, BitAnd),
+            (TokenKind::DoubleLess, Shl),
+            (TokenKind::DoubleGreater, Shr),
+            (TokenKind::Plus, Add),
+            (TokenKind::Minus, Sub),
+            (TokenKind::Asterisk, Mult),
+            (TokenKind::Slash, Div),
+            (TokenKind::DoubleSlash, IntDiv),
+            (TokenKind::Percent, Mod),
+            (TokenKind::DoubleAsterisk, Pow),
+        ];
+        infix
+            .into_iter()
+            .fold(Self::new(), |table, (kind, op)| {
+                table.infix(kind, op.precedence(), op.associativity(), op)
+            })
+            .prefix(TokenKind::Plus, PREFIX_PRECEDENCE, UnaryOperationKind::Plus)
+            .prefix(
+                TokenKind::Minus,
+                PREFIX_PRECEDENCE,
+                UnaryOperationKind::Minus,
+            )
+            .prefix(
+                TokenKind::Tilde,
+                PREFIX_PRECEDENCE,
+                UnaryOperationKind::BitNot,
+            )
+    }
+
+    pub fn prefix(
+        mut self,
+        kind: TokenKind,
+        precedence: u8,
+        operation: UnaryOperationKind,
+    ) -> Self {
+        self.prefix.insert(
+            kind,
+            UnaryOperator {
+                precedence,
+                operation,
+            },
+        );
+        self
+    }
+
+    pub fn infix(
+        mut self,
+        kind: TokenKind,
+        precedence: u8,
+        associativity: Associativity,
+        operation: BinaryOperationKind,
+    ) -> Self {
+        self.infix.insert(
+            kind,
+            InfixOperator {
+                precedence,
+                associativity,
+                operation,
+            },
+        );
+        self
+    }
+
+    pub fn postfix(
+        mut self,
+        kind: TokenKind,
+        precedence: u8,
+        operation: UnaryOperationKind,
+    ) -> Self {
+        self.postfix.insert(
+            kind,
+            UnaryOperator {
+                precedence,
+                operation,
+            },
+        );
+        self
+    }
+
+    pub fn get_prefix(&self, kind: &TokenKind) -> Option<&UnaryOperator> {
+        self.prefix.get(kind)
+    }
+
+    pub fn get_infix(&self, kind: &TokenKind) -> Option<&InfixOperator> {
+        self.infix.get(kind)
+    }
+
+    pub fn get_postfix(&self, kind: &TokenKind) -> Option<&UnaryOperator> {
+        self.postfix.get(kind)
     }
 }
 
-pub fn parse_expr<Tokens>(tokens: &mut Peekable<Tokens>) -> Result<Ast, ParseError>
-where
-    Tokens: Iterator<Item = Token>,
-{
-    parse_expr3(tokens)
+// 演算子表に従って式を組み立てるPrattパーサ
+#[derive(Debug, Clone)]
+pub struct Parser {
+    table: OperatorTable,
 }
 
-pub fn parse_expr3<Tokens>(tokens: &mut Peekable<Tokens>) -> Result<Ast, ParseError>
-where
-    Tokens: Iterator<Item = Token>,
-{
-    // parse_left_binopに渡す関数を定義する
-    fn parse_expr3_op<Tokens>(tokens: &mut Peekable<Tokens>) -> Result<BinaryOperation, ParseError>
+impl Default for Parser {
+    fn default() -> Self {
+        Self::new(OperatorTable::standard())
+    }
+}
+
+impl Parser {
+    pub fn new(table: OperatorTable) -> Self {
+        Self { table }
+    }
+
+    pub fn parse(&self, tokens: Vec<Token>) -> Result<Ast, ParseError> {
+        // エラーは入力の先頭から順に記録されるので、最初のものが従来のエラーにあたる
+        let (ast, mut errors) = self.parse_recovering(tokens);
+        if errors.is_empty() {
+            Ok(ast)
+        } else {
+            Err(errors.swap_remove(0))
+        }
+    }
+
+    // エラーがあっても最後まで読み進め、壊れた部分をAstkind::Errorで置き換えた木と
+    // 見つかったエラーをすべて返す
+    pub fn parse_recovering(&self, tokens: Vec<Token>) -> (Ast, Vec<ParseError>) {
+        let mut tokens = tokens.into_iter().peekable();
+        let mut errors = Vec::new();
+        let ret = self.parse_expr_bp(&mut tokens, 0, &mut errors, &Location(0, 0));
+        // 余ったトークンは報告して読み飛ばし、続きに式があればそこのエラーも集める
+        while let Some(token) = tokens.next() {
+            let loc = token.loc.clone();
+            errors.push(ParseError::RedundantExpression(token));
+            if tokens.peek().is_some() {
+                self.parse_expr_bp(&mut tokens, 0, &mut errors, &loc);
+            }
+        }
+        (ret, errors)
+    }
+
+    // STATEMENT = "let" IDENT "=" EXPR | EXPR
+    pub fn parse_statement(&self, tokens: Vec<Token>) -> Result<Statement, ParseError> {
+        let mut tokens = tokens.into_iter().peekable();
+        let ret = match tokens.peek().map(|tok| &tok.value) {
+            Some(TokenKind::Let) => {
+                let let_loc = tokens.next().unwrap().loc;
+                let name = match tokens.next() {
+                    Some(Token {
+                        value: TokenKind::Ident(name),
+                        loc: _,
+                    }) => name,
+                    Some(tok) => return Err(ParseError::UnexpectedToken(tok)),
+                    None => return Err(ParseError::Eof),
+                };
+                match tokens.next() {
+                    Some(Token {
+                        value: TokenKind::Equal,
+                        loc: _,
+                    }) => (),
+                    Some(tok) => return Err(ParseError::UnexpectedToken(tok)),
+                    None => return Err(ParseError::Eof),
+                }
+                let e = self.parse_expr(&mut tokens)?;
+                let loc = let_loc.merge(&e.loc);
+                Statement::let_(&name, e, loc)
+            }
+            _ => Statement::expression(self.parse_expr(&mut tokens)?),
+        };
+        match tokens.next() {
+            Some(token) => Err(ParseError::RedundantExpression(token)),
+            None => Ok(ret),
+        }
+    }
+
+    pub fn parse_expr<Tokens>(&self, tokens: &mut Peekable<Tokens>) -> Result<Ast, ParseError>
     where
         Tokens: Iterator<Item = Token>,
     {
-        let op = tokens
-            .peek()
-            // イテレータの終わり入力の終端なのでエラーを出す
-            .ok_or(ParseError::Eof)
-            // エラーを返すかもしれない値をつなげる
-            .and_then(|tok| match tok.value {
-                TokenKind::Plus => Ok(BinaryOperation::add(tok.loc.clone())),
-                TokenKind::Minus => Ok(BinaryOperation::sub(tok.loc.clone())),
-                _ => Err(ParseError::NotOperator(tok.clone())),
-            })?;
-        tokens.next();
-        Ok(op)
-    }
-    parse_left_binop(tokens, parse_expr2, parse_expr3_op)
-}
+        let mut errors = Vec::new();
+        let e = self.parse_expr_bp(tokens, 0, &mut errors, &Location(0, 0));
+        match errors.into_iter().next() {
+            Some(err) => Err(err),
+            None => Ok(e),
+        }
+    }
 
-pub fn parse_expr2<Tokens>(tokens: &mut Peekable<Tokens>) -> Result<Ast, ParseError>
-where
-    Tokens: Iterator<Item = Token>,
-{
-    fn parse_expr2_op<Tokens>(tokens: &mut Peekable<Tokens>) -> Result<BinaryOperation, ParseError>
+    // min_precedence以上の強さの演算子だけを読み進める
+    // 優先順位u8::MAXの左結合の演算子でも一段強い値を表せるよう、u16で持つ
+    // prevは直前に読んだトークンの位置で、入力が途切れたときのError節点の位置に使う
+    fn parse_expr_bp<Tokens>(
+        &self,
+        tokens: &mut Peekable<Tokens>,
+        min_precedence: u16,
+        errors: &mut Vec<ParseError>,
+        prev: &Location,
+    ) -> Ast
+    where
+        Tokens: Iterator<Item = Token>,
+    {
+        let mut e = self.parse_prefix(tokens, errors, prev);
+        while let Some(tok) = tokens.peek() {
+            let kind = &tok.value;
+            if let Some(op) = self.table.get_postfix(kind) {
+                if u16::from(op.precedence) < min_precedence {
+                    break;
+                }
+                let op = UnaryOperation::new(op.operation.clone(), tokens.next().unwrap().loc);
+                let loc = e.loc.merge(&op.loc);
+                e = Ast::unary_operation(op, e, loc);
+            } else if let Some(op) = self.table.get_infix(kind) {
+                if u16::from(op.precedence) < min_precedence {
+                    break;
+                }
+                // 左結合なら右辺は一段強い演算子だけを読む
+                let next_precedence = match op.associativity {
+                    Associativity::Left => u16::from(op.precedence) + 1,
+                    Associativity::Right => u16::from(op.precedence),
+                };
+                let op = BinaryOperation::new(op.operation.clone(), tokens.next().unwrap().loc);
+                let r = self.parse_expr_bp(tokens, next_precedence, errors, &op.loc);
+                let loc = e.loc.merge(&r.loc);
+                e = Ast::binary_operation(op, e, r, loc);
+            } else {
+                // 演算子でなければ式はここまで
+                break;
+            }
+        }
+        e
+    }
+
+    // PREFIX = PREFIX_OP EXPR | ATOM
+    fn parse_prefix<Tokens>(
+        &self,
+        tokens: &mut Peekable<Tokens>,
+        errors: &mut Vec<ParseError>,
+        prev: &Location,
+    ) -> Ast
     where
         Tokens: Iterator<Item = Token>,
     {
         let op = tokens
             .peek()
-            .ok_or(ParseError::Eof)
-            .and_then(|tok| match tok.value {
-                TokenKind::Asterisk => Ok(BinaryOperation::mult(tok.loc.clone())),
-                TokenKind::Slash => Ok(BinaryOperation::div(tok.loc.clone())),
-                _ => Err(ParseError::NotOperator(tok.clone())),
-            })?;
-        tokens.next();
-        Ok(op)
-    }
-    parse_left_binop(tokens, parse_expr1, parse_expr2_op)
-}
+            .and_then(|tok| self.table.get_prefix(&tok.value));
+        match op {
+            Some(op) => {
+                let precedence = u16::from(op.precedence);
+                let op = UnaryOperation::new(op.operation.clone(), tokens.next().unwrap().loc);
+                let e = self.parse_expr_bp(tokens, precedence, errors, &op.loc);
+                let loc = op.loc.merge(&e.loc);
+                Ast::unary_operation(op, e, loc)
+            }
+            None => self.parse_atom(tokens, errors, prev),
+        }
+    }
 
-pub fn parse_left_binop<Tokens>(
-    tokens: &mut Peekable<Tokens>,
-    subexpr_paser: fn(&mut Peekable<Tokens>) -> Result<Ast, ParseError>,
-    op_parser: fn(&mut Peekable<Tokens>) -> Result<BinaryOperation, ParseError>,
-) -> Result<Ast, ParseError>
-where
-    Tokens: Iterator<Item = Token>,
-{
-    let mut e = subexpr_paser(tokens)?;
-    loop {
-        match tokens.peek() {
-            Some(_) => {
-                let op = match op_parser(tokens) {
-                    Ok(op) => op,
-                    // ここでパースに失敗したのはこれ以上中置演算子がないという意味
-                    Err(_) => break,
+    // ATOM = UNUMBER | FLOAT | IDENT | "(" EXPR ")"
+    fn parse_atom<Tokens>(
+        &self,
+        tokens: &mut Peekable<Tokens>,
+        errors: &mut Vec<ParseError>,
+        prev: &Location,
+    ) -> Ast
+    where
+        Tokens: Iterator<Item = Token>,
+    {
+        let tok = match tokens.peek() {
+            Some(tok) => tok,
+            None => {
+                errors.push(ParseError::Eof);
+                return Ast::error(Location(prev.1, prev.1));
+            }
+        };
+        // )や中置演算子は読まずに残し、そこから解析を再開する
+        if tok.value == TokenKind::Rparen || self.table.get_infix(&tok.value).is_some() {
+            let tok = tok.clone();
+            let loc = tok.loc.clone();
+            errors.push(ParseError::NotExpression(tok));
+            return Ast::error(loc);
+        }
+        let tok = tokens.next().unwrap();
+        match tok.value {
+            // UNUMBER
+            TokenKind::Number(n, radix) => Ast::number_with_radix(n, radix, tok.loc),
+            TokenKind::Float(n) => Ast::new(Astkind::Float(n), tok.loc),
+            // | IDENT
+            TokenKind::Ident(name) => Ast::new(Astkind::Variable(name), tok.loc),
+            // 字句解析のエラーは報告済みなのでそのままError節点にする
+            TokenKind::Error => Ast::error(tok.loc),
+            // | "(",EXPR,")";
+            TokenKind::Lparen => {
+                let e = self.parse_expr_bp(tokens, 0, errors, &tok.loc);
+                // 括弧の節点は(から)までを位置とする。)がなければ中の式の終わりまで
+                let end = match tokens.next() {
+                    Some(Token {
+                        value: TokenKind::Rparen,
+                        loc,
+                    }) => loc,
+                    Some(t) => {
+                        errors.push(ParseError::RedundantExpression(t));
+                        // 対応する)まで読み飛ばす
+                        skip_to_rparen(tokens).unwrap_or_else(|| e.loc.clone())
+                    }
+                    None => {
+                        let loc = e.loc.clone();
+                        errors.push(ParseError::UnclosedOpenParen(tok.clone()));
+                        loc
+                    }
                 };
-                let r = subexpr_paser(tokens)?;
-                let loc = e.loc.merge(&r.loc);
-                e = Ast::binary_operation(op, e, r, loc)
+                let loc = tok.loc.merge(&end);
+                Ast::paren(e, loc)
+            }
+            _ => {
+                let loc = tok.loc.clone();
+                errors.push(ParseError::NotExpression(tok));
+                Ast::error(loc)
             }
-            _ => break,
         }
     }
-    Ok(e)
 }
 
-// expr1
-pub fn parse_expr1<Tokens>(tokens: &mut Peekable<Tokens>) -> Result<Ast, ParseError>
+// 括弧の入れ子を数えながら、今開いている括弧を閉じる)まで読み飛ばし、その位置を返す
+fn skip_to_rparen<Tokens>(tokens: &mut Peekable<Tokens>) -> Option<Location>
 where
     Tokens: Iterator<Item = Token>,
 {
-    match tokens.peek().map(|tok| tok.value) {
-        Some(TokenKind::Plus) | Some(TokenKind::Minus) => {
-            // ("+"|"-")
-            let op = match tokens.next() {
-                Some(Token {
-                    value: TokenKind::Plus,
-                    loc,
-                }) => UnaryOperation::plus(loc),
-                Some(Token {
-                    value: TokenKind::Minus,
-                    loc,
-                }) => UnaryOperation::minus(loc),
-                _ => unreachable!(),
-            };
-            // ,ATOM
-            let e = parse_atom(tokens)?;
-            let loc = op.loc.merge(&e.loc);
-            Ok(Ast::unary_operation(op, e, loc))
+    let mut depth = 0;
+    for tok in tokens {
+        match tok.value {
+            TokenKind::Lparen => depth += 1,
+            TokenKind::Rparen if depth == 0 => return Some(tok.loc),
+            TokenKind::Rparen => depth -= 1,
+            _ => (),
         }
-        // |ATOM
-        _ => parse_atom(tokens),
     }
+    None
 }
-// ATOM
-pub fn parse_atom<Tokens>(tokens: &mut Peekable<Tokens>) -> Result<Ast, ParseError>
-where
-    Tokens: Iterator<Item = Token>,
-{
-    tokens
-        .next()
-        .ok_or(ParseError::Eof)
-        .and_then(|tok| match tok.value {
-            // UNUMBER
-            TokenKind::Number(n) => Ok(Ast::new(Astkind::Number(n), tok.loc)),
-            // | "(",EXPR3,")";
-            TokenKind::Lparen => {
-                let e = parse_expr(tokens)?;
-                match tokens.next() {
-                    Some(Token {
-                        value: TokenKind::Rparen,
-                        loc: _,
-                    }) => Ok(e),
-                    Some(t) => Err(ParseError::RedundantExpression(t)),
-                    _ => Err(ParseError::UnclosedOpenParen(tok)),
-                }
-            }
-            _ => Err(ParseError::NotExpression(tok)),
-        })
+
+// 標準の演算子表で式をパースする
+pub fn parse(tokens: Vec<Token>) -> Result<Ast, ParseError> {
+    Parser::default().parse(tokens)
+}
+
+// 標準の演算子表で、エラーから回復しながら式をパースする
+pub fn parse_recovering(tokens: Vec<Token>) -> (Ast, Vec<ParseError>) {
+    Parser::default().parse_recovering(tokens)
+}
+
+// 標準の演算子表で文をパースする
+pub fn parse_statement(tokens: Vec<Token>) -> Result<Statement, ParseError> {
+    Parser::default().parse_statement(tokens)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::token::Location;
+    use crate::token::Radix;
 
     #[test]
     fn test_parser() {
@@ -192,6 +437,620 @@ mod tests {
             ))
         )
     }
+
+    #[test]
+    fn test_parse_paren() {
+        // (1 + 2) * 3
+        let ast = parse(vec![
+            Token::lparen(Location(0, 1)),
+            Token::number(1, Location(1, 2)),
+            Token::plus(Location(3, 4)),
+            Token::number(2, Location(5, 6)),
+            Token::rparen(Location(6, 7)),
+            Token::asterisk(Location(8, 9)),
+            Token::number(3, Location(10, 11)),
+        ]);
+        assert_eq!(
+            ast,
+            Ok(Ast::binary_operation(
+                BinaryOperation::mult(Location(8, 9)),
+                Ast::paren(
+                    Ast::binary_operation(
+                        BinaryOperation::add(Location(3, 4)),
+                        Ast::number(1, Location(1, 2)),
+                        Ast::number(2, Location(5, 6)),
+                        Location(1, 6),
+                    ),
+                    Location(0, 7),
+                ),
+                Ast::number(3, Location(10, 11)),
+                Location(0, 11),
+            ))
+        );
+    }
+
+    #[test]
+    fn test_parse_keeps_radix() {
+        // 0x1F & 0b1
+        let ast = parse(vec![
+            Token::number_with_radix(0x1F, Radix::Hexadecimal, Location(0, 4)),
+            Token::ampersand(Location(5, 6)),
+            Token::number_with_radix(1, Radix::Binary, Location(7, 10)),
+        ]);
+        assert_eq!(
+            ast,
+            Ok(Ast::binary_operation(
+                BinaryOperation::new(crate::ast::BinaryOperationKind::BitAnd, Location(5, 6)),
+                Ast::number_with_radix(0x1F, Radix::Hexadecimal, Location(0, 4)),
+                Ast::number_with_radix(1, Radix::Binary, Location(7, 10)),
+                Location(0, 10),
+            ))
+        );
+    }
+
+    #[test]
+    fn test_parse_power_right_associative() {
+        // 2 ** 3 ** 2
+        let ast = parse(vec![
+            Token::number(2, Location(0, 1)),
+            Token::double_asterisk(Location(2, 4)),
+            Token::number(3, Location(5, 6)),
+            Token::double_asterisk(Location(7, 9)),
+            Token::number(2, Location(10, 11)),
+        ]);
+        assert_eq!(
+            ast,
+            Ok(Ast::binary_operation(
+                BinaryOperation::pow(Location(2, 4)),
+                Ast::number(2, Location(0, 1)),
+                Ast::binary_operation(
+                    BinaryOperation::pow(Location(7, 9)),
+                    Ast::number(3, Location(5, 6)),
+                    Ast::number(2, Location(10, 11)),
+                    Location(5, 11),
+                ),
+                Location(0, 11),
+            ))
+        );
+    }
+
+    #[test]
+    fn test_parse_power_with_unary_minus() {
+        // -2 ** 2
+        let ast = parse(vec![
+            Token::minus(Location(0, 1)),
+            Token::number(2, Location(1, 2)),
+            Token::double_asterisk(Location(3, 5)),
+            Token::number(2, Location(6, 7)),
+        ]);
+        assert_eq!(
+            ast,
+            Ok(Ast::unary_operation(
+                UnaryOperation::minus(Location(0, 1)),
+                Ast::binary_operation(
+                    BinaryOperation::pow(Location(3, 5)),
+                    Ast::number(2, Location(1, 2)),
+                    Ast::number(2, Location(6, 7)),
+                    Location(1, 7),
+                ),
+                Location(0, 7),
+            ))
+        );
+
+        // 2 ** -1
+        let ast = parse(vec![
+            Token::number(2, Location(0, 1)),
+            Token::double_asterisk(Location(2, 4)),
+            Token::minus(Location(5, 6)),
+            Token::number(1, Location(6, 7)),
+        ]);
+        assert_eq!(
+            ast,
+            Ok(Ast::binary_operation(
+                BinaryOperation::pow(Location(2, 4)),
+                Ast::number(2, Location(0, 1)),
+                Ast::unary_operation(
+                    UnaryOperation::minus(Location(5, 6)),
+                    Ast::number(1, Location(6, 7)),
+                    Location(5, 7),
+                ),
+                Location(0, 7),
+            ))
+        );
+    }
+
+    #[test]
+    fn test_parse_power_binds_tighter_than_mult() {
+        // 2 * 3 ** 2
+        let ast = parse(vec![
+            Token::number(2, Location(0, 1)),
+            Token::asterisk(Location(2, 3)),
+            Token::number(3, Location(4, 5)),
+            Token::double_asterisk(Location(6, 8)),
+            Token::number(2, Location(9, 10)),
+        ]);
+        assert_eq!(
+            ast,
+            Ok(Ast::binary_operation(
+                BinaryOperation::mult(Location(2, 3)),
+                Ast::number(2, Location(0, 1)),
+                Ast::binary_operation(
+                    BinaryOperation::pow(Location(6, 8)),
+                    Ast::number(3, Location(4, 5)),
+                    Ast::number(2, Location(9, 10)),
+                    Location(4, 10),
+                ),
+                Location(0, 10),
+            ))
+        );
+    }
+
+    #[test]
+    fn test_parse_c_like_precedence() {
+        // 1 | 2 ^ 3 & 4 << 5 + 6 % 7
+        let ast = parse(vec![
+            Token::number(1, Location(0, 1)),
+            Token::pipe(Location(2, 3)),
+            Token::number(2, Location(4, 5)),
+            Token::caret(Location(6, 7)),
+            Token::number(3, Location(8, 9)),
+            Token::ampersand(Location(10, 11)),
+            Token::number(4, Location(12, 13)),
+            Token::double_less(Location(14, 16)),
+            Token::number(5, Location(17, 18)),
+            Token::plus(Location(19, 20)),
+            Token::number(6, Location(21, 22)),
+            Token::percent(Location(23, 24)),
+            Token::number(7, Location(25, 26)),
+        ]);
+        assert_eq!(
+            ast,
+            Ok(Ast::binary_operation(
+                BinaryOperation::bit_or(Location(2, 3)),
+                Ast::number(1, Location(0, 1)),
+                Ast::binary_operation(
+                    BinaryOperation::bit_xor(Location(6, 7)),
+                    Ast::number(2, Location(4, 5)),
+                    Ast::binary_operation(
+                        BinaryOperation::bit_and(Location(10, 11)),
+                        Ast::number(3, Location(8, 9)),
+                        Ast::binary_operation(
+                            BinaryOperation::shl(Location(14, 16)),
+                            Ast::number(4, Location(12, 13)),
+                            Ast::binary_operation(
+                                BinaryOperation::add(Location(19, 20)),
+                                Ast::number(5, Location(17, 18)),
+                                Ast::binary_operation(
+                                    BinaryOperation::modulo(Location(23, 24)),
+                                    Ast::number(6, Location(21, 22)),
+                                    Ast::number(7, Location(25, 26)),
+                                    Location(21, 26),
+                                ),
+                                Location(17, 26),
+                            ),
+                            Location(12, 26),
+                        ),
+                        Location(8, 26),
+                    ),
+                    Location(4, 26),
+                ),
+                Location(0, 26),
+            ))
+        );
+    }
+
+    #[test]
+    fn test_parse_bit_not() {
+        // ~1 xor 2
+        let ast = parse(vec![
+            Token::tilde(Location(0, 1)),
+            Token::number(1, Location(1, 2)),
+            Token::xor(Location(3, 6)),
+            Token::number(2, Location(7, 8)),
+        ]);
+        assert_eq!(
+            ast,
+            Ok(Ast::binary_operation(
+                BinaryOperation::bit_xor(Location(3, 6)),
+                Ast::unary_operation(
+                    UnaryOperation::bit_not(Location(0, 1)),
+                    Ast::number(1, Location(1, 2)),
+                    Location(0, 2),
+                ),
+                Ast::number(2, Location(7, 8)),
+                Location(0, 8),
+            ))
+        );
+    }
+
+    #[test]
+    fn test_parse_with_custom_table() {
+        use crate::ast::BinaryOperationKind;
+
+        // +が*より強く、右結合になる表
+        let table = OperatorTable::new()
+            .infix(
+                TokenKind::Asterisk,
+                1,
+                Associativity::Left,
+                BinaryOperationKind::Mult,
+            )
+            .infix(
+                TokenKind::Plus,
+                2,
+                Associativity::Right,
+                BinaryOperationKind::Add,
+            );
+        let parser = Parser::new(table);
+        // 1 * 2 + 3 + 4
+        let ast = parser.parse(vec![
+            Token::number(1, Location(0, 1)),
+            Token::asterisk(Location(2, 3)),
+            Token::number(2, Location(4, 5)),
+            Token::plus(Location(6, 7)),
+            Token::number(3, Location(8, 9)),
+            Token::plus(Location(10, 11)),
+            Token::number(4, Location(12, 13)),
+        ]);
+        assert_eq!(
+            ast,
+            Ok(Ast::binary_operation(
+                BinaryOperation::mult(Location(2, 3)),
+                Ast::number(1, Location(0, 1)),
+                Ast::binary_operation(
+                    BinaryOperation::add(Location(6, 7)),
+                    Ast::number(2, Location(4, 5)),
+                    Ast::binary_operation(
+                        BinaryOperation::add(Location(10, 11)),
+                        Ast::number(3, Location(8, 9)),
+                        Ast::number(4, Location(12, 13)),
+                        Location(8, 13),
+                    ),
+                    Location(4, 13),
+                ),
+                Location(0, 13),
+            ))
+        );
+
+        // 表にない演算子は使えない
+        assert_eq!(
+            parser.parse(vec![
+                Token::minus(Location(0, 1)),
+                Token::number(1, Location(1, 2)),
+            ]),
+            Err(ParseError::NotExpression(Token::minus(Location(0, 1))))
+        );
+
+        // 最大の優先順位の左結合の演算子も左から結合する
+        let parser = Parser::new(OperatorTable::new().infix(
+            TokenKind::Minus,
+            u8::MAX,
+            Associativity::Left,
+            BinaryOperationKind::Sub,
+        ));
+        // 1 - 2 - 3
+        let ast = parser.parse(vec![
+            Token::number(1, Location(0, 1)),
+            Token::minus(Location(2, 3)),
+            Token::number(2, Location(4, 5)),
+            Token::minus(Location(6, 7)),
+            Token::number(3, Location(8, 9)),
+        ]);
+        assert_eq!(
+            ast,
+            Ok(Ast::binary_operation(
+                BinaryOperation::sub(Location(6, 7)),
+                Ast::binary_operation(
+                    BinaryOperation::sub(Location(2, 3)),
+                    Ast::number(1, Location(0, 1)),
+                    Ast::number(2, Location(4, 5)),
+                    Location(0, 5),
+                ),
+                Ast::number(3, Location(8, 9)),
+                Location(0, 9),
+            ))
+        );
+    }
+
+    #[test]
+    fn test_parse_postfix_operator() {
+        use crate::ast::UnaryOperationKind;
+
+        // ~を後置のビット反転として扱う
+        let table =
+            OperatorTable::standard().postfix(TokenKind::Tilde, 9, UnaryOperationKind::BitNot);
+        let parser = Parser::new(table);
+        // 1 + 2~
+        let ast = parser.parse(vec![
+            Token::number(1, Location(0, 1)),
+            Token::plus(Location(2, 3)),
+            Token::number(2, Location(4, 5)),
+            Token::tilde(Location(5, 6)),
+        ]);
+        assert_eq!(
+            ast,
+            Ok(Ast::binary_operation(
+                BinaryOperation::add(Location(2, 3)),
+                Ast::number(1, Location(0, 1)),
+                Ast::unary_operation(
+                    UnaryOperation::bit_not(Location(5, 6)),
+                    Ast::number(2, Location(4, 5)),
+                    Location(4, 6),
+                ),
+                Location(0, 6),
+            ))
+        );
+    }
+
+    #[test]
+    fn test_parse_nested_unary() {
+        // --5
+        let ast = parse(vec![
+            Token::minus(Location(0, 1)),
+            Token::minus(Location(1, 2)),
+            Token::number(5, Location(2, 3)),
+        ]);
+        assert_eq!(
+            ast,
+            Ok(Ast::unary_operation(
+                UnaryOperation::minus(Location(0, 1)),
+                Ast::unary_operation(
+                    UnaryOperation::minus(Location(1, 2)),
+                    Ast::number(5, Location(2, 3)),
+                    Location(1, 3),
+                ),
+                Location(0, 3),
+            ))
+        );
+
+        // - -5 * 2
+        let ast = parse(vec![
+            Token::minus(Location(0, 1)),
+            Token::minus(Location(2, 3)),
+            Token::number(5, Location(3, 4)),
+            Token::asterisk(Location(5, 6)),
+            Token::number(2, Location(7, 8)),
+        ]);
+        assert_eq!(
+            ast,
+            Ok(Ast::binary_operation(
+                BinaryOperation::mult(Location(5, 6)),
+                Ast::unary_operation(
+                    UnaryOperation::minus(Location(0, 1)),
+                    Ast::unary_operation(
+                        UnaryOperation::minus(Location(2, 3)),
+                        Ast::number(5, Location(3, 4)),
+                        Location(2, 4),
+                    ),
+                    Location(0, 4),
+                ),
+                Ast::number(2, Location(7, 8)),
+                Location(0, 8),
+            ))
+        );
+
+        // -+~5 ** 2
+        let ast = parse(vec![
+            Token::minus(Location(0, 1)),
+            Token::plus(Location(1, 2)),
+            Token::tilde(Location(2, 3)),
+            Token::number(5, Location(3, 4)),
+            Token::double_asterisk(Location(5, 7)),
+            Token::number(2, Location(8, 9)),
+        ]);
+        assert_eq!(
+            ast,
+            Ok(Ast::unary_operation(
+                UnaryOperation::minus(Location(0, 1)),
+                Ast::unary_operation(
+                    UnaryOperation::plus(Location(1, 2)),
+                    Ast::unary_operation(
+                        UnaryOperation::bit_not(Location(2, 3)),
+                        Ast::binary_operation(
+                            BinaryOperation::pow(Location(5, 7)),
+                            Ast::number(5, Location(3, 4)),
+                            Ast::number(2, Location(8, 9)),
+                            Location(3, 9),
+                        ),
+                        Location(2, 9),
+                    ),
+                    Location(1, 9),
+                ),
+                Location(0, 9),
+            ))
+        );
+
+        // - だけでは式にならない
+        assert_eq!(
+            parse(vec![
+                Token::minus(Location(0, 1)),
+                Token::minus(Location(1, 2)),
+            ]),
+            Err(ParseError::Eof)
+        );
+    }
+
+    #[test]
+    fn test_parse_recovering() {
+        // (1 + * 2) + 3 )
+        let (ast, errors) = parse_recovering(vec![
+            Token::lparen(Location(0, 1)),
+            Token::number(1, Location(1, 2)),
+            Token::plus(Location(3, 4)),
+            Token::asterisk(Location(5, 6)),
+            Token::number(2, Location(7, 8)),
+            Token::rparen(Location(8, 9)),
+            Token::plus(Location(10, 11)),
+            Token::number(3, Location(12, 13)),
+            Token::rparen(Location(14, 15)),
+        ]);
+        assert_eq!(
+            ast,
+            Ast::binary_operation(
+                BinaryOperation::add(Location(10, 11)),
+                Ast::paren(
+                    Ast::binary_operation(
+                        BinaryOperation::add(Location(3, 4)),
+                        Ast::number(1, Location(1, 2)),
+                        Ast::binary_operation(
+                            BinaryOperation::mult(Location(5, 6)),
+                            Ast::error(Location(5, 6)),
+                            Ast::number(2, Location(7, 8)),
+                            Location(5, 8),
+                        ),
+                        Location(1, 8),
+                    ),
+                    Location(0, 9),
+                ),
+                Ast::number(3, Location(12, 13)),
+                Location(0, 13),
+            )
+        );
+        assert_eq!(
+            errors,
+            vec![
+                ParseError::NotExpression(Token::asterisk(Location(5, 6))),
+                ParseError::RedundantExpression(Token::rparen(Location(14, 15))),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_recovering_synchronizes_at_rparen() {
+        // (1 2 3) * (4 +
+        let (ast, errors) = parse_recovering(vec![
+            Token::lparen(Location(0, 1)),
+            Token::number(1, Location(1, 2)),
+            Token::number(2, Location(3, 4)),
+            Token::number(3, Location(5, 6)),
+            Token::rparen(Location(6, 7)),
+            Token::asterisk(Location(8, 9)),
+            Token::lparen(Location(10, 11)),
+            Token::number(4, Location(11, 12)),
+            Token::plus(Location(13, 14)),
+        ]);
+        assert_eq!(
+            ast,
+            Ast::binary_operation(
+                BinaryOperation::mult(Location(8, 9)),
+                // )まで読み飛ばした括弧は)を含み、閉じていない括弧は中の式の終わりまで
+                Ast::paren(Ast::number(1, Location(1, 2)), Location(0, 7)),
+                Ast::paren(
+                    Ast::binary_operation(
+                        BinaryOperation::add(Location(13, 14)),
+                        Ast::number(4, Location(11, 12)),
+                        Ast::error(Location(14, 14)),
+                        Location(11, 14),
+                    ),
+                    Location(10, 14),
+                ),
+                Location(0, 14),
+            )
+        );
+        assert_eq!(
+            errors,
+            vec![
+                ParseError::RedundantExpression(Token::number(2, Location(3, 4))),
+                ParseError::Eof,
+                ParseError::UnclosedOpenParen(Token::lparen(Location(10, 11))),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_returns_first_error() {
+        // 1 + * 2 )
+        let tokens = vec![
+            Token::number(1, Location(0, 1)),
+            Token::plus(Location(2, 3)),
+            Token::asterisk(Location(4, 5)),
+            Token::number(2, Location(6, 7)),
+            Token::rparen(Location(8, 9)),
+        ];
+        assert_eq!(parse_recovering(tokens.clone()).1.len(), 2);
+        assert_eq!(
+            parse(tokens),
+            Err(ParseError::NotExpression(Token::asterisk(Location(4, 5))))
+        );
+    }
+
+    #[test]
+    fn test_parse_error_token() {
+        // 1 + <error>
+        let (ast, errors) = parse_recovering(vec![
+            Token::number(1, Location(0, 1)),
+            Token::plus(Location(2, 3)),
+            Token::error(Location(4, 6)),
+        ]);
+        assert_eq!(
+            ast,
+            Ast::binary_operation(
+                BinaryOperation::add(Location(2, 3)),
+                Ast::number(1, Location(0, 1)),
+                Ast::error(Location(4, 6)),
+                Location(0, 6),
+            )
+        );
+        assert_eq!(errors, vec![]);
+    }
+
+    #[test]
+    fn test_parse_statement() {
+        // let x = y + 1
+        let statement = parse_statement(vec![
+            Token::let_(Location(0, 3)),
+            Token::ident("x", Location(4, 5)),
+            Token::equal(Location(6, 7)),
+            Token::ident("y", Location(8, 9)),
+            Token::plus(Location(10, 11)),
+            Token::number(1, Location(12, 13)),
+        ]);
+        assert_eq!(
+            statement,
+            Ok(Statement::let_(
+                "x",
+                Ast::binary_operation(
+                    BinaryOperation::add(Location(10, 11)),
+                    Ast::variable("y", Location(8, 9)),
+                    Ast::number(1, Location(12, 13)),
+                    Location(8, 13),
+                ),
+                Location(0, 13),
+            ))
+        );
+
+        // x
+        let statement = parse_statement(vec![Token::ident("x", Location(0, 1))]);
+        assert_eq!(
+            statement,
+            Ok(Statement::expression(Ast::variable("x", Location(0, 1))))
+        );
+    }
+
+    #[test]
+    fn test_parse_statement_error() {
+        // let 1 = 2
+        assert_eq!(
+            parse_statement(vec![
+                Token::let_(Location(0, 3)),
+                Token::number(1, Location(4, 5)),
+                Token::equal(Location(6, 7)),
+                Token::number(2, Location(8, 9)),
+            ]),
+            Err(ParseError::UnexpectedToken(Token::number(
+                1,
+                Location(4, 5)
+            )))
+        );
+        // let x
+        assert_eq!(
+            parse_statement(vec![
+                Token::let_(Location(0, 3)),
+                Token::ident("x", Location(4, 5)),
+            ]),
+            Err(ParseError::Eof)
+        );
+    }
 }
 // parse_expr3()は書き換えたので古い１
 // pub fn parse_expr3<Tokens>(tokens: &mut Peekable<Tokens>) -> Result<Ast, ParseError>
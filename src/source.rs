@@ -0,0 +1,258 @@
+use crate::token::Location;
+use std::fmt;
+
+// 行と列。どちらも0始まり
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LineColumn {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl LineColumn {
+    pub fn new(line: usize, column: usize) -> Self {
+        Self { line, column }
+    }
+
+    // 人が読むための1始まりの行と列
+    pub fn one_based(self) -> Self {
+        Self::new(self.line + 1, self.column + 1)
+    }
+}
+
+// 1始まりに直した上で 行:列 の形で表示する
+impl fmt::Display for LineColumn {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let p = self.one_based();
+        write!(f, "{}:{}", p.line, p.column)
+    }
+}
+
+// 入力全体と各行の開始位置を持ち、Locationのバイト位置を行と列に変換する
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SourceFile {
+    text: String,
+    // 各行の先頭のバイト位置
+    line_starts: Vec<usize>,
+}
+
+impl SourceFile {
+    pub fn new(text: &str) -> Self {
+        let line_starts = std::iter::once(0)
+            .chain(text.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        Self {
+            text: text.to_string(),
+            line_starts,
+        }
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn line_count(&self) -> usize {
+        self.line_starts.len()
+    }
+
+    // offsetを含む行の番号(0始まり)
+    pub fn line_index(&self, offset: usize) -> usize {
+        let offset = self.floor_char_boundary(offset);
+        match self.line_starts.binary_search(&offset) {
+            Ok(line) => line,
+            Err(line) => line - 1,
+        }
+    }
+
+    // 行の内容。末尾の改行は含まない
+    pub fn line_text(&self, line: usize) -> &str {
+        let start = self.line_starts[line];
+        let end = self
+            .line_starts
+            .get(line + 1)
+            .map_or(self.text.len(), |&next| next - 1);
+        self.text[start..end].trim_end_matches('\r')
+    }
+
+    // 列をUTF-8のバイト数で数える
+    pub fn line_column(&self, offset: usize) -> LineColumn {
+        let (line, prefix) = self.line_prefix(offset);
+        LineColumn::new(line, prefix.len())
+    }
+
+    // 列を文字数で数える。人に見せる位置にはこれを使う
+    pub fn line_column_chars(&self, offset: usize) -> LineColumn {
+        let (line, prefix) = self.line_prefix(offset);
+        LineColumn::new(line, prefix.chars().count())
+    }
+
+    // 列をUTF-16のコード単位で数える。多くのエディタの位置はこの単位になっている
+    pub fn line_column_utf16(&self, offset: usize) -> LineColumn {
+        let (line, prefix) = self.line_prefix(offset);
+        LineColumn::new(line, prefix.chars().map(char::len_utf16).sum())
+    }
+
+    // Locationの始まりと終わりを行と列にする
+    pub fn location_range(&self, loc: &Location) -> (LineColumn, LineColumn) {
+        (self.line_column_chars(loc.0), self.line_column_chars(loc.1))
+    }
+
+    // offsetを含む行の番号と、行頭からoffsetまでの文字列
+    fn line_prefix(&self, offset: usize) -> (usize, &str) {
+        let offset = self.floor_char_boundary(offset);
+        let line = self.line_index(offset);
+        (line, &self.text[self.line_starts[line]..offset])
+    }
+
+    // 位置が入力の外や文字の途中を指していたら文字の先頭まで戻す
+    pub fn floor_char_boundary(&self, offset: usize) -> usize {
+        let mut offset = offset.min(self.text.len());
+        while !self.text.is_char_boundary(offset) {
+            offset -= 1;
+        }
+        offset
+    }
+}
+
+// SourceDatabaseに登録したファイルの番号
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct FileId(pub usize);
+
+// どのファイルの位置かを含めた範囲
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Span {
+    pub file: FileId,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(file: FileId, loc: &Location) -> Self {
+        Self {
+            file,
+            start: loc.0,
+            end: loc.1,
+        }
+    }
+
+    // ファイル内での位置
+    pub fn location(&self) -> Location {
+        Location(self.start, self.end)
+    }
+}
+
+// 複数のファイルの名前と内容を持つ
+#[derive(Debug, Clone, Default)]
+pub struct SourceDatabase {
+    files: Vec<(String, SourceFile)>,
+}
+
+impl SourceDatabase {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // ファイルを登録して番号を返す
+    pub fn add(&mut self, path: &str, text: &str) -> FileId {
+        self.files.push((path.to_string(), SourceFile::new(text)));
+        FileId(self.files.len() - 1)
+    }
+
+    pub fn path(&self, file: FileId) -> &str {
+        &self.files[file.0].0
+    }
+
+    pub fn file(&self, file: FileId) -> &SourceFile {
+        &self.files[file.0].1
+    }
+
+    pub fn file_ids(&self) -> impl Iterator<Item = FileId> {
+        (0..self.files.len()).map(FileId)
+    }
+
+    // Spanの開始位置を パス:行:列 の形にする
+    pub fn describe(&self, span: &Span) -> String {
+        let position = self.file(span.file).line_column_chars(span.start);
+        format!("{}:{}", self.path(span.file), position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_line_index() {
+        let file = SourceFile::new("1 +\n2 *\n\n3");
+        assert_eq!(file.line_count(), 4);
+        assert_eq!(file.line_index(0), 0);
+        assert_eq!(file.line_index(3), 0);
+        assert_eq!(file.line_index(4), 1);
+        assert_eq!(file.line_index(8), 2);
+        assert_eq!(file.line_index(9), 3);
+        // 入力の終端は最後の行
+        assert_eq!(file.line_index(100), 3);
+    }
+
+    #[test]
+    fn test_line_text() {
+        let file = SourceFile::new("1 +\r\n2 *\n3");
+        assert_eq!(file.line_text(0), "1 +");
+        assert_eq!(file.line_text(1), "2 *");
+        assert_eq!(file.line_text(2), "3");
+    }
+
+    #[test]
+    fn test_line_column() {
+        let file = SourceFile::new("1 +\n(22 * 3");
+        assert_eq!(file.line_column(5), LineColumn::new(1, 1));
+        assert_eq!(file.line_column(5).one_based(), LineColumn::new(2, 2));
+        assert_eq!(file.line_column(5).to_string(), "2:2");
+    }
+
+    #[test]
+    fn test_line_column_crlf() {
+        let file = SourceFile::new("1 +\r\n22 * $\r\n");
+        let offset = file.text().find('$').unwrap();
+        assert_eq!(file.line_count(), 3);
+        assert_eq!(file.line_index(3), 0);
+        assert_eq!(file.line_column(offset), LineColumn::new(1, 5));
+        assert_eq!(file.line_text(1), "22 * $");
+    }
+
+    #[test]
+    fn test_line_column_multibyte() {
+        // éは2バイトでUTF-16では1単位、😀は4バイトでUTF-16では2単位
+        let file = SourceFile::new("x\né😀 + $");
+        let offset = file.text().find('$').unwrap();
+        assert_eq!(file.line_column(offset), LineColumn::new(1, 9));
+        assert_eq!(file.line_column_chars(offset), LineColumn::new(1, 5));
+        assert_eq!(file.line_column_utf16(offset), LineColumn::new(1, 6));
+        // 文字の途中を指していたら文字の先頭とみなす
+        assert_eq!(file.line_column_chars(4), LineColumn::new(1, 1));
+    }
+
+    #[test]
+    fn test_location_range() {
+        let file = SourceFile::new("1 +\n22 * 3");
+        assert_eq!(
+            file.location_range(&Location(4, 6)),
+            (LineColumn::new(1, 0), LineColumn::new(1, 2))
+        );
+    }
+
+    #[test]
+    fn test_source_database() {
+        let mut db = SourceDatabase::new();
+        let consts = db.add("consts.expr", "let a = 1\nlet b = 2");
+        let main = db.add("main.expr", "a + b");
+        assert_eq!(consts, FileId(0));
+        assert_eq!(main, FileId(1));
+        assert_eq!(db.path(main), "main.expr");
+        assert_eq!(db.file(consts).line_text(1), "let b = 2");
+        assert_eq!(db.file_ids().collect::<Vec<_>>(), vec![consts, main]);
+
+        let span = Span::new(consts, &Location(14, 15));
+        assert_eq!(span.location(), Location(14, 15));
+        assert_eq!(db.describe(&span), "consts.expr:2:5");
+    }
+}
@@ -1,3 +1,6 @@
+use std::fmt;
+use std::hash::{Hash, Hasher};
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Location(pub usize, pub usize);
 
@@ -9,6 +12,23 @@ impl Location {
         use std::cmp::{max, min};
         Location(min(self.0, other.0), max(self.1, other.1))
     }
+
+    // inputのうちこの位置にあたる部分。正規化した字句解析では元の綴りが取り出せる
+    pub fn slice<'a>(&self, input: &'a str) -> &'a str {
+        &input[self.0..self.1]
+    }
+
+    // バイト単位の位置を、inputの先頭から数えた文字単位の位置に変換する
+    pub fn char_range(&self, input: &str) -> (usize, usize) {
+        let count = |p: usize| input.char_indices().take_while(|(i, _)| *i < p).count();
+        (count(self.0), count(self.1))
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}-{}", self.0, self.1)
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -23,22 +43,152 @@ impl<T> Annotation<T> {
     }
 }
 
+// f64はEqやHashを実装していないので、ビット列で比較・ハッシュするラッパーを使う
+#[derive(Debug, Clone, Copy)]
+pub struct Float(pub f64);
+
+impl PartialEq for Float {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.to_bits() == other.0.to_bits()
+    }
+}
+
+impl Eq for Float {}
+
+impl Hash for Float {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.to_bits().hash(state)
+    }
+}
+
+impl fmt::Display for Float {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Debugの方は3.0や1e23のように浮動小数点数とわかる形で出力される
+        write!(f, "{:?}", self.0)
+    }
+}
+
+// 整数リテラルの基数
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Copy)]
+pub enum Radix {
+    // 0b
+    Binary,
+    // 0o
+    Octal,
+    Decimal,
+    // 0x
+    Hexadecimal,
+}
+
+impl Radix {
+    pub fn value(self) -> u32 {
+        match self {
+            Radix::Binary => 2,
+            Radix::Octal => 8,
+            Radix::Decimal => 10,
+            Radix::Hexadecimal => 16,
+        }
+    }
+
+    pub fn prefix(self) -> &'static str {
+        match self {
+            Radix::Binary => "0b",
+            Radix::Octal => "0o",
+            Radix::Decimal => "",
+            Radix::Hexadecimal => "0x",
+        }
+    }
+
+    // nをこの基数の接頭辞と数字で書く
+    pub fn format(self, n: u64) -> String {
+        match self {
+            Radix::Binary => format!("0b{:b}", n),
+            Radix::Octal => format!("0o{:o}", n),
+            Radix::Decimal => format!("{}", n),
+            Radix::Hexadecimal => format!("0x{:X}", n),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum TokenKind {
-    Number(u64),
+    // 元の表記を再現できるよう基数も持っておく
+    Number(u64, Radix),
+    // 小数や指数を含む数値
+    Float(Float),
     Plus,
     Minus,
     Asterisk,
+    // **
+    DoubleAsterisk,
     Slash,
+    // //
+    DoubleSlash,
+    Percent,
+    Ampersand,
+    Pipe,
+    Caret,
+    // キーワード xor (^と同じ意味)
+    Xor,
+    // <<
+    DoubleLess,
+    // >>
+    DoubleGreater,
+    Tilde,
     Lparen,
     Rparen,
+    // 変数名
+    Ident(String),
+    // キーワード let
+    Let,
+    Equal,
+    // 字句解析に失敗した部分
+    Error,
 }
 
 pub type Token = Annotation<TokenKind>;
 
+impl fmt::Display for TokenKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::TokenKind::*;
+        match self {
+            Number(n, radix) => f.write_str(&radix.format(*n)),
+            Float(n) => n.fmt(f),
+            Plus => write!(f, "+"),
+            Minus => write!(f, "-"),
+            Asterisk => write!(f, "*"),
+            DoubleAsterisk => write!(f, "**"),
+            Slash => write!(f, "/"),
+            DoubleSlash => write!(f, "//"),
+            Percent => write!(f, "%"),
+            Ampersand => write!(f, "&"),
+            Pipe => write!(f, "|"),
+            Caret => write!(f, "^"),
+            Xor => write!(f, "xor"),
+            DoubleLess => write!(f, "<<"),
+            DoubleGreater => write!(f, ">>"),
+            Tilde => write!(f, "~"),
+            Lparen => write!(f, "("),
+            Rparen => write!(f, ")"),
+            Ident(name) => f.write_str(name),
+            Let => write!(f, "let"),
+            Equal => write!(f, "="),
+            Error => write!(f, "<error>"),
+        }
+    }
+}
+
 impl Token {
     pub fn number(n: u64, loc: Location) -> Self {
-        Self::new(TokenKind::Number(n), loc)
+        Self::new(TokenKind::Number(n, Radix::Decimal), loc)
+    }
+
+    pub fn number_with_radix(n: u64, radix: Radix, loc: Location) -> Self {
+        Self::new(TokenKind::Number(n, radix), loc)
+    }
+
+    pub fn float(n: f64, loc: Location) -> Self {
+        Self::new(TokenKind::Float(Float(n)), loc)
     }
 
     pub fn plus(loc: Location) -> Self {
@@ -53,10 +203,50 @@ impl Token {
         Self::new(TokenKind::Asterisk, loc)
     }
 
+    pub fn double_asterisk(loc: Location) -> Self {
+        Self::new(TokenKind::DoubleAsterisk, loc)
+    }
+
     pub fn slash(loc: Location) -> Self {
         Self::new(TokenKind::Slash, loc)
     }
 
+    pub fn double_slash(loc: Location) -> Self {
+        Self::new(TokenKind::DoubleSlash, loc)
+    }
+
+    pub fn percent(loc: Location) -> Self {
+        Self::new(TokenKind::Percent, loc)
+    }
+
+    pub fn ampersand(loc: Location) -> Self {
+        Self::new(TokenKind::Ampersand, loc)
+    }
+
+    pub fn pipe(loc: Location) -> Self {
+        Self::new(TokenKind::Pipe, loc)
+    }
+
+    pub fn caret(loc: Location) -> Self {
+        Self::new(TokenKind::Caret, loc)
+    }
+
+    pub fn xor(loc: Location) -> Self {
+        Self::new(TokenKind::Xor, loc)
+    }
+
+    pub fn double_less(loc: Location) -> Self {
+        Self::new(TokenKind::DoubleLess, loc)
+    }
+
+    pub fn double_greater(loc: Location) -> Self {
+        Self::new(TokenKind::DoubleGreater, loc)
+    }
+
+    pub fn tilde(loc: Location) -> Self {
+        Self::new(TokenKind::Tilde, loc)
+    }
+
     pub fn lparen(loc: Location) -> Self {
         Self::new(TokenKind::Lparen, loc)
     }
@@ -64,12 +254,97 @@ impl Token {
     pub fn rparen(loc: Location) -> Self {
         Self::new(TokenKind::Rparen, loc)
     }
+
+    pub fn ident(name: &str, loc: Location) -> Self {
+        Self::new(TokenKind::Ident(name.to_string()), loc)
+    }
+
+    pub fn let_(loc: Location) -> Self {
+        Self::new(TokenKind::Let, loc)
+    }
+
+    pub fn equal(loc: Location) -> Self {
+        Self::new(TokenKind::Equal, loc)
+    }
+
+    pub fn error(loc: Location) -> Self {
+        Self::new(TokenKind::Error, loc)
+    }
+}
+
+// 空白のように構文には関わらないが、元の入力を復元するのに必要な部分
+// 元の綴りをそのまま持つ
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum TriviaKind {
+    Whitespace(String),
+    // # か、被演算子の後ろ以外にある // から行末まで。改行は含まない
+    LineComment(String),
+    // /* */ で囲まれた部分。入れ子にできる
+    BlockComment(String),
+}
+
+pub type Trivia = Annotation<TriviaKind>;
+
+impl Trivia {
+    pub fn whitespace(s: &str, loc: Location) -> Self {
+        Self::new(TriviaKind::Whitespace(s.to_string()), loc)
+    }
+
+    pub fn line_comment(s: &str, loc: Location) -> Self {
+        Self::new(TriviaKind::LineComment(s.to_string()), loc)
+    }
+
+    pub fn block_comment(s: &str, loc: Location) -> Self {
+        Self::new(TriviaKind::BlockComment(s.to_string()), loc)
+    }
+
+    pub fn text(&self) -> &str {
+        match &self.value {
+            TriviaKind::Whitespace(s)
+            | TriviaKind::LineComment(s)
+            | TriviaKind::BlockComment(s) => s,
+        }
+    }
+}
+
+// 直前のトリビアと元の綴りを持つトークン。並べて書き出すと入力に戻る
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct LosslessToken {
+    pub leading: Vec<Trivia>,
+    pub token: Token,
+    pub text: String,
+}
+
+impl fmt::Display for LosslessToken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for trivia in &self.leading {
+            f.write_str(trivia.text())?;
+        }
+        f.write_str(&self.text)
+    }
+}
+
+// 全角文字などを置き換えてから読んだトークンと、置き換える前の元の綴り
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct NormalizedToken {
+    pub token: Token,
+    pub original: String,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum LexErrorKind {
     InvalidChar(char),
+    // 数値リテラルがu64に収まらない
+    NumberOutOfRange,
+    // 小数点や指数の後に数字がない
+    MalformedNumber,
+    // 0xなどの接頭辞の後に数字がない
+    MissingDigits,
+    // 基数に合わない数字
+    InvalidDigit(char),
     Eof,
+    // /* が閉じられないまま入力が終わった。位置は開始の /* を指す
+    UnterminatedComment,
 }
 
 pub type LexError = Annotation<LexErrorKind>;
@@ -79,7 +354,50 @@ impl LexError {
         Self::new(LexErrorKind::InvalidChar(c), loc)
     }
 
+    pub fn number_out_of_range(loc: Location) -> Self {
+        Self::new(LexErrorKind::NumberOutOfRange, loc)
+    }
+
+    pub fn malformed_number(loc: Location) -> Self {
+        Self::new(LexErrorKind::MalformedNumber, loc)
+    }
+
+    pub fn missing_digits(loc: Location) -> Self {
+        Self::new(LexErrorKind::MissingDigits, loc)
+    }
+
+    pub fn invalid_digit(c: char, loc: Location) -> Self {
+        Self::new(LexErrorKind::InvalidDigit(c), loc)
+    }
+
     pub fn eof(loc: Location) -> Self {
         Self::new(LexErrorKind::Eof, loc)
     }
+
+    pub fn unterminated_comment(loc: Location) -> Self {
+        Self::new(LexErrorKind::UnterminatedComment, loc)
+    }
+}
+
+impl fmt::Display for LexErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::LexErrorKind::*;
+        match self {
+            InvalidChar(c) => write!(f, "invalid char '{}'", c),
+            NumberOutOfRange => write!(f, "number literal is out of range"),
+            MalformedNumber => write!(f, "digits are expected"),
+            MissingDigits => write!(f, "digits are expected after the prefix"),
+            InvalidDigit(c) => write!(f, "invalid digit '{}' for the radix", c),
+            Eof => write!(f, "End of file"),
+            UnterminatedComment => write!(f, "comment is not closed"),
+        }
+    }
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.loc, self.value)
+    }
 }
+
+impl std::error::Error for LexError {}
@@ -0,0 +1,182 @@
+use sample_parser::ast::{BinaryOperation, UnaryOperation};
+use sample_parser::diagnostics::render;
+use sample_parser::formatter::{format_source, Style};
+use sample_parser::token::LexErrorKind;
+use sample_parser::{
+    eval, eval_file, lex, parse, parse_statement_str, parse_str, Ast, Environment, Error,
+    EvalError, LexError, Location, ParseError, SourceDatabase, Span, Token, Value,
+};
+
+#[test]
+fn test_parse_str() {
+    assert_eq!(
+        parse_str("1 + -2"),
+        Ok(Ast::binary_operation(
+            BinaryOperation::add(Location(2, 3)),
+            Ast::number(1, Location(0, 1)),
+            Ast::unary_operation(
+                UnaryOperation::minus(Location(4, 5)),
+                Ast::number(2, Location(5, 6)),
+                Location(4, 6),
+            ),
+            Location(0, 6),
+        ))
+    );
+}
+
+#[test]
+fn test_parse_str_is_lex_then_parse() {
+    let input = "(1 + 2) * 3 / 4";
+    assert_eq!(
+        parse_str(input).unwrap(),
+        parse(lex(input).unwrap()).unwrap()
+    );
+}
+
+#[test]
+fn test_parse_str_errors() {
+    assert_eq!(
+        parse_str("1 $ 2"),
+        Err(Error::Lexer(LexError::invalid_char('$', Location(2, 3))))
+    );
+    assert_eq!(
+        parse_str("(1 + 2"),
+        Err(Error::Parser(ParseError::UnclosedOpenParen(Token::lparen(
+            Location(0, 1)
+        ))))
+    );
+    assert_eq!(parse_str("1 +"), Err(Error::Parser(ParseError::Eof)));
+
+    let e = parse_str("99999999999999999999").unwrap_err();
+    assert!(matches!(
+        e,
+        Error::Lexer(LexError {
+            value: LexErrorKind::NumberOutOfRange,
+            ..
+        })
+    ));
+}
+
+#[test]
+fn test_eval_end_to_end() {
+    assert_eq!(
+        eval(&parse_str("(1 + 2) * 3 - 4 / 2").unwrap()),
+        Ok(Value::Int(7))
+    );
+    assert_eq!(
+        eval(&parse_str("6.02e23 / 2").unwrap()),
+        Ok(Value::Float(3.01e23))
+    );
+}
+
+#[test]
+fn test_render_error() {
+    let input = "1 + * 2";
+    let e = parse_str(input).unwrap_err();
+    assert_eq!(
+        render(input, &e),
+        "error: '*' is not a start of expression\n --> 1:5\n1 + * 2\n    ^"
+    );
+}
+
+#[test]
+fn test_environment_across_lines() {
+    let mut env = Environment::new();
+    let mut run = |line: &str| env.eval_statement(&parse_statement_str(line).unwrap());
+    assert_eq!(run("let width = 0x10"), Ok(Value::Int(16)));
+    assert_eq!(run("let height = 2.5"), Ok(Value::Float(2.5)));
+    assert_eq!(run("width * height"), Ok(Value::Float(40.0)));
+    assert_eq!(
+        run("depth"),
+        Err(EvalError::unbound_variable("depth", Location(0, 5)))
+    );
+}
+
+#[test]
+fn test_eval_files_together() {
+    let mut db = SourceDatabase::new();
+    let consts = db.add(
+        "consts.expr",
+        "// sizes\nlet kb = 1 << 10 # bytes\n\nlet mb = kb * kb /* bytes */\n",
+    );
+    let main = db.add("main.expr", "let size = 3 * mb\nsize // kb");
+    let mut env = Environment::new();
+    assert_eq!(
+        eval_file(&db, consts, &mut env),
+        Ok(Some(Value::Int(1 << 20)))
+    );
+    assert_eq!(
+        eval_file(&db, main, &mut env),
+        Ok(Some(Value::Int(3 << 10)))
+    );
+}
+
+#[test]
+fn test_eval_file_multiline_statement() {
+    let mut db = SourceDatabase::new();
+    let main = db.add(
+        "main.expr",
+        "let total =\n    1 +\n    2 * (3 +\n    4)\ntotal * 2",
+    );
+    let mut env = Environment::new();
+    assert_eq!(eval_file(&db, main, &mut env), Ok(Some(Value::Int(30))));
+}
+
+#[test]
+fn test_eval_file_error_location() {
+    let mut db = SourceDatabase::new();
+    let consts = db.add("consts.expr", "let kb = 1024");
+    let main = db.add("main.expr", "let x = kb\nx * mb");
+    let mut env = Environment::new();
+    eval_file(&db, consts, &mut env).unwrap();
+
+    let e = eval_file(&db, main, &mut env).unwrap_err();
+    assert_eq!(
+        e.span(),
+        Some(Span {
+            file: main,
+            start: 15,
+            end: 17,
+        })
+    );
+    assert_eq!(
+        e.render(&db),
+        "error: variable 'mb' is not defined\n --> main.expr:2:5\nx * mb\n    ^^"
+    );
+
+    // 1行に2つの文は書けない
+    let bad = db.add("bad.expr", "1 2\n");
+    let e = eval_file(&db, bad, &mut env).unwrap_err();
+    assert_eq!(
+        e.render(&db),
+        "error: redundant expression starting at '2'\n --> bad.expr:1:3\n1 2\n  ^"
+    );
+}
+
+#[test]
+fn test_paren_span() {
+    let ast = parse_str("(1+2)").unwrap();
+    assert_eq!(ast.loc, Location(0, 5));
+    let inner = ast.clone().strip_parens();
+    assert_eq!(inner.loc, Location(1, 4));
+    assert_eq!(eval(&ast), eval(&inner));
+}
+
+#[test]
+fn test_formatted_file_evaluates_the_same() {
+    let input = "let base=1000 # ms\nlet total = base*60 + (base*60)*60 + base // 7 - 1\ntotal";
+    let style = Style {
+        width: 24,
+        ..Style::default()
+    };
+    let formatted = format_source(input, &style).unwrap();
+    assert!(formatted.lines().count() > 3);
+
+    let mut db = SourceDatabase::new();
+    let original = db.add("original.expr", input);
+    let formatted = db.add("formatted.expr", &formatted);
+    assert_eq!(
+        eval_file(&db, original, &mut Environment::new()),
+        eval_file(&db, formatted, &mut Environment::new())
+    );
+}